@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS usersdata;
+DROP TABLE IF EXISTS users;
+DROP SCHEMA IF EXISTS amourithm;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_users_unverified_created_at;
+ALTER TABLE users DROP COLUMN IF EXISTS email_verified_at;
@@ -0,0 +1,8 @@
+-- Track when a user verified their email address (NULL = not verified yet)
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMPTZ NULL;
+
+-- Accounts created before verification was tracked are treated as verified
+UPDATE users SET email_verified_at = created_at WHERE email_verified_at IS NULL;
+
+-- Speeds up the clean-up job that removes stale unverified registrations
+CREATE INDEX IF NOT EXISTS idx_users_unverified_created_at ON users(created_at) WHERE email_verified_at IS NULL;
@@ -0,0 +1,27 @@
+-- Every swipe a user makes on another profile
+CREATE TABLE IF NOT EXISTS likes (
+    id UUID PRIMARY KEY,
+    liker_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,  -- User who swiped
+    likee_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,  -- User who was swiped on
+    kind VARCHAR(20) NOT NULL,                                       -- Like, Pass or SuperLike
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT likes_pair_unique UNIQUE (liker_id, likee_id),        -- One swipe per pair, re-swiping updates it
+    CONSTRAINT likes_no_self CHECK (liker_id <> likee_id),
+    CONSTRAINT likes_kind_check CHECK (kind IN ('Like', 'Pass', 'SuperLike'))
+);
+
+-- Find who liked a given user
+CREATE INDEX IF NOT EXISTS idx_likes_likee_id ON likes(likee_id);
+
+-- Mutual likes. The pair is stored ordered so each match exists only once.
+CREATE TABLE IF NOT EXISTS matches (
+    id UUID PRIMARY KEY,
+    user_one_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    user_two_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT matches_pair_unique UNIQUE (user_one_id, user_two_id),
+    CONSTRAINT matches_pair_ordered CHECK (user_one_id < user_two_id)
+);
+
+-- Lookups by the second user, the unique constraint already covers user_one_id
+CREATE INDEX IF NOT EXISTS idx_matches_user_two_id ON matches(user_two_id);
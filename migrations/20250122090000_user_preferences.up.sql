@@ -0,0 +1,14 @@
+-- What each user is looking for, used to filter and rank discovery
+CREATE TABLE IF NOT EXISTS user_preferences (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    wanted_genders TEXT[] NOT NULL DEFAULT '{}',  -- Genders the user wants to see, empty means any
+    min_age INT NOT NULL DEFAULT 18,
+    max_age INT NOT NULL DEFAULT 50,
+    max_distance_km INT NULL,                     -- No limit when NULL
+    intent VARCHAR(20) NULL,                      -- Relationship intent
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT preferences_age_check CHECK (min_age >= 18 AND max_age <= 50 AND min_age <= max_age),
+    CONSTRAINT preferences_distance_check CHECK (max_distance_km IS NULL OR max_distance_km > 0),
+    CONSTRAINT preferences_intent_check CHECK (intent IN ('LongTerm', 'ShortTerm', 'Casual', 'Friendship', 'NotSure'))
+);
@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS idx_usersdata_location;
+
+ALTER TABLE usersdata
+    DROP COLUMN IF EXISTS latitude,
+    DROP COLUMN IF EXISTS longitude,
+    DROP COLUMN IF EXISTS location_updated_at;
+
+-- The extensions are left installed, other databases on the server may use them
@@ -0,0 +1,14 @@
+-- Distance queries use the cube/earthdistance extensions that ship with Postgres
+CREATE EXTENSION IF NOT EXISTS cube;
+CREATE EXTENSION IF NOT EXISTS earthdistance;
+
+-- Coordinates are stored rounded to two decimals (about 1 km) for privacy
+ALTER TABLE usersdata
+    ADD COLUMN IF NOT EXISTS latitude DOUBLE PRECISION NULL,
+    ADD COLUMN IF NOT EXISTS longitude DOUBLE PRECISION NULL,
+    ADD COLUMN IF NOT EXISTS location_updated_at TIMESTAMPTZ NULL,
+    ADD CONSTRAINT usersdata_latitude_check CHECK (latitude BETWEEN -90 AND 90),
+    ADD CONSTRAINT usersdata_longitude_check CHECK (longitude BETWEEN -180 AND 180);
+
+-- Radius searches go through earth_box, which this index serves
+CREATE INDEX IF NOT EXISTS idx_usersdata_location ON usersdata USING gist (ll_to_earth(latitude, longitude));
@@ -0,0 +1,4 @@
+-- Fails if a longer URL has been stored since
+ALTER TABLE usersdata ALTER COLUMN profile_picture_url TYPE VARCHAR(256);
+
+DROP TABLE IF EXISTS photos;
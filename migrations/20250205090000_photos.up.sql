@@ -0,0 +1,20 @@
+-- Profile photos, shown in `position` order
+CREATE TABLE IF NOT EXISTS photos (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    storage_key VARCHAR(256) NOT NULL,         -- Key of the file in the blob store
+    url VARCHAR(512) NOT NULL,                 -- Public URL of the file
+    content_type VARCHAR(50) NOT NULL,
+    position INT NOT NULL,
+    is_primary BOOLEAN NOT NULL DEFAULT FALSE,  -- The one used as profile_picture_url
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    -- Deferred so a reorder can swap positions within one transaction
+    CONSTRAINT photos_position_unique UNIQUE (user_id, position) DEFERRABLE INITIALLY DEFERRED,
+    CONSTRAINT photos_position_check CHECK (position >= 0)
+);
+
+-- At most one primary photo per user
+CREATE UNIQUE INDEX IF NOT EXISTS idx_photos_primary ON photos(user_id) WHERE is_primary;
+
+-- Photo URLs can be longer than the original column allowed
+ALTER TABLE usersdata ALTER COLUMN profile_picture_url TYPE VARCHAR(512);
@@ -0,0 +1,13 @@
+DROP INDEX IF EXISTS idx_photos_processing;
+
+-- Photos that never got a URL can't satisfy the NOT NULL again
+DELETE FROM photos WHERE url IS NULL;
+
+ALTER TABLE photos
+    DROP CONSTRAINT IF EXISTS photos_status_check,
+    DROP COLUMN IF EXISTS status,
+    DROP COLUMN IF EXISTS thumbnail_key,
+    DROP COLUMN IF EXISTS thumbnail_url,
+    DROP COLUMN IF EXISTS medium_key,
+    DROP COLUMN IF EXISTS medium_url,
+    ALTER COLUMN url SET NOT NULL;
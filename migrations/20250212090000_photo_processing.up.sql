@@ -0,0 +1,16 @@
+-- Uploads are resized into variants in the background. Until that finishes
+-- a photo is Processing and has no URLs.
+ALTER TABLE photos
+    ADD COLUMN IF NOT EXISTS status VARCHAR(20) NOT NULL DEFAULT 'Processing',
+    ADD COLUMN IF NOT EXISTS thumbnail_key VARCHAR(256) NULL,
+    ADD COLUMN IF NOT EXISTS thumbnail_url VARCHAR(512) NULL,
+    ADD COLUMN IF NOT EXISTS medium_key VARCHAR(256) NULL,
+    ADD COLUMN IF NOT EXISTS medium_url VARCHAR(512) NULL,
+    ALTER COLUMN url DROP NOT NULL,
+    ADD CONSTRAINT photos_status_check CHECK (status IN ('Processing', 'Ready', 'Failed'));
+
+-- Photos uploaded before processing existed are kept as they are
+UPDATE photos SET status = 'Ready';
+
+-- The stale photo sweep looks for old Processing rows
+CREATE INDEX IF NOT EXISTS idx_photos_processing ON photos(created_at) WHERE status = 'Processing';
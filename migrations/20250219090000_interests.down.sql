@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS user_interests;
+DROP TABLE IF EXISTS interests;
@@ -0,0 +1,52 @@
+-- Curated catalogue of interests users can pick from
+CREATE TABLE IF NOT EXISTS interests (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    slug VARCHAR(50) UNIQUE NOT NULL,  -- Stable identifier, e.g. "board-games"
+    name VARCHAR(50) NOT NULL,         -- Display name
+    category VARCHAR(50) NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Interests picked by each user
+CREATE TABLE IF NOT EXISTS user_interests (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    interest_id UUID NOT NULL REFERENCES interests(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, interest_id)
+);
+
+-- Find everyone sharing an interest
+CREATE INDEX IF NOT EXISTS idx_user_interests_interest_id ON user_interests(interest_id);
+
+INSERT INTO interests (slug, name, category) VALUES
+    ('hiking', 'Hiking', 'Outdoors'),
+    ('camping', 'Camping', 'Outdoors'),
+    ('cycling', 'Cycling', 'Outdoors'),
+    ('running', 'Running', 'Outdoors'),
+    ('climbing', 'Climbing', 'Outdoors'),
+    ('photography', 'Photography', 'Arts'),
+    ('painting', 'Painting', 'Arts'),
+    ('music', 'Music', 'Arts'),
+    ('theatre', 'Theatre', 'Arts'),
+    ('writing', 'Writing', 'Arts'),
+    ('cooking', 'Cooking', 'Food & Drink'),
+    ('baking', 'Baking', 'Food & Drink'),
+    ('coffee', 'Coffee', 'Food & Drink'),
+    ('wine', 'Wine', 'Food & Drink'),
+    ('vegan-food', 'Vegan Food', 'Food & Drink'),
+    ('movies', 'Movies', 'Entertainment'),
+    ('gaming', 'Gaming', 'Entertainment'),
+    ('board-games', 'Board Games', 'Entertainment'),
+    ('anime', 'Anime', 'Entertainment'),
+    ('podcasts', 'Podcasts', 'Entertainment'),
+    ('travel', 'Travel', 'Lifestyle'),
+    ('yoga', 'Yoga', 'Lifestyle'),
+    ('fitness', 'Fitness', 'Lifestyle'),
+    ('pets', 'Pets', 'Lifestyle'),
+    ('volunteering', 'Volunteering', 'Lifestyle'),
+    ('reading', 'Reading', 'Learning'),
+    ('languages', 'Languages', 'Learning'),
+    ('science', 'Science', 'Learning'),
+    ('history', 'History', 'Learning'),
+    ('technology', 'Technology', 'Learning')
+ON CONFLICT (slug) DO NOTHING;
@@ -0,0 +1,26 @@
+-- One conversation per match, removed together with the match on unmatch
+CREATE TABLE IF NOT EXISTS conversations (
+    id UUID PRIMARY KEY,
+    match_id UUID UNIQUE NOT NULL REFERENCES matches(id) ON DELETE CASCADE,
+    user_one_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    user_two_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    last_message_at TIMESTAMPTZ NULL,
+    CONSTRAINT conversations_pair_ordered CHECK (user_one_id < user_two_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_conversations_user_one_id ON conversations(user_one_id);
+CREATE INDEX IF NOT EXISTS idx_conversations_user_two_id ON conversations(user_two_id);
+
+CREATE TABLE IF NOT EXISTS messages (
+    id UUID PRIMARY KEY,
+    conversation_id UUID NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    sender_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    body TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    read_at TIMESTAMPTZ NULL,  -- When the other user read it
+    CONSTRAINT messages_body_check CHECK (char_length(body) BETWEEN 1 AND 2000)
+);
+
+-- History is paged newest first
+CREATE INDEX IF NOT EXISTS idx_messages_conversation_created ON messages(conversation_id, created_at DESC, id DESC);
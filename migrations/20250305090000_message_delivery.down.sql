@@ -0,0 +1,8 @@
+DROP TABLE IF EXISTS inbox_events;
+
+DROP INDEX IF EXISTS idx_messages_unread;
+
+ALTER TABLE messages
+    DROP CONSTRAINT IF EXISTS messages_status_check,
+    DROP COLUMN IF EXISTS status,
+    DROP COLUMN IF EXISTS delivered_at;
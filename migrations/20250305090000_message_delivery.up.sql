@@ -0,0 +1,22 @@
+-- Delivery state of a message: Sent, Delivered to one of the recipient's
+-- devices, then Read
+ALTER TABLE messages
+    ADD COLUMN IF NOT EXISTS status VARCHAR(20) NOT NULL DEFAULT 'Sent',
+    ADD COLUMN IF NOT EXISTS delivered_at TIMESTAMPTZ NULL,
+    ADD CONSTRAINT messages_status_check CHECK (status IN ('Sent', 'Delivered', 'Read'));
+
+UPDATE messages SET status = 'Read', delivered_at = read_at WHERE read_at IS NOT NULL;
+
+-- Unread counts and pending deliveries only look at messages not yet read
+CREATE INDEX IF NOT EXISTS idx_messages_unread ON messages(conversation_id, sender_id) WHERE status <> 'Read';
+
+-- Events kept until one of the user's WebSocket sessions has received them,
+-- so users who weren't connected get them on their next connection
+CREATE TABLE IF NOT EXISTS inbox_events (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_inbox_events_user_created ON inbox_events(user_id, created_at);
@@ -0,0 +1,6 @@
+ALTER TABLE users
+    DROP COLUMN IF EXISTS suspended_until,
+    DROP COLUMN IF EXISTS suspension_reason;
+
+DROP TABLE IF EXISTS reports;
+DROP TABLE IF EXISTS blocks;
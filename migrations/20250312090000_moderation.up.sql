@@ -0,0 +1,37 @@
+-- A block hides the two users from each other in both directions
+CREATE TABLE IF NOT EXISTS blocks (
+    blocker_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    blocked_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (blocker_id, blocked_id),
+    CONSTRAINT blocks_not_self CHECK (blocker_id <> blocked_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_blocks_blocked_id ON blocks(blocked_id);
+
+-- Moderation queue: Open -> Triaged -> Resolved or Dismissed
+CREATE TABLE IF NOT EXISTS reports (
+    id UUID PRIMARY KEY,
+    reporter_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    reported_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    reason VARCHAR(30) NOT NULL,
+    details TEXT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'Open',
+    moderator_note TEXT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    resolved_at TIMESTAMPTZ NULL,
+    CONSTRAINT reports_not_self CHECK (reporter_id <> reported_id),
+    CONSTRAINT reports_reason_check CHECK (reason IN ('Spam', 'FakeProfile', 'Harassment', 'InappropriateContent', 'Underage', 'Scam', 'Other')),
+    CONSTRAINT reports_status_check CHECK (status IN ('Open', 'Triaged', 'Resolved', 'Dismissed')),
+    CONSTRAINT reports_details_check CHECK (char_length(details) <= 1000)
+);
+
+-- One pending report per reporter and reported user
+CREATE UNIQUE INDEX IF NOT EXISTS idx_reports_pending_pair ON reports(reporter_id, reported_id) WHERE status IN ('Open', 'Triaged');
+CREATE INDEX IF NOT EXISTS idx_reports_status_created ON reports(status, created_at);
+CREATE INDEX IF NOT EXISTS idx_reports_reported_id ON reports(reported_id);
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS suspended_until TIMESTAMPTZ NULL,
+    ADD COLUMN IF NOT EXISTS suspension_reason TEXT NULL;
@@ -0,0 +1,5 @@
+ALTER TABLE users
+    DROP CONSTRAINT IF EXISTS users_status_check,
+    DROP COLUMN IF EXISTS status,
+    DROP COLUMN IF EXISTS banned_at,
+    DROP COLUMN IF EXISTS ban_reason;
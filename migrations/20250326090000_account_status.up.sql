@@ -0,0 +1,7 @@
+-- Banned accounts stay banned until an admin lifts it. Suspensions keep
+-- using suspended_until and lift by themselves once it has passed.
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS status VARCHAR(20) NOT NULL DEFAULT 'Active',
+    ADD COLUMN IF NOT EXISTS banned_at TIMESTAMPTZ NULL,
+    ADD COLUMN IF NOT EXISTS ban_reason TEXT NULL,
+    ADD CONSTRAINT users_status_check CHECK (status IN ('Active', 'Banned'));
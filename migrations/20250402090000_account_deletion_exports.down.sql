@@ -0,0 +1,11 @@
+DROP TABLE IF EXISTS data_exports;
+
+DROP INDEX IF EXISTS idx_users_deleted_at;
+
+-- Accounts still waiting to be purged can't stay Deleted under the old check
+DELETE FROM users WHERE status = 'Deleted';
+
+ALTER TABLE users
+    DROP COLUMN IF EXISTS deleted_at,
+    DROP CONSTRAINT IF EXISTS users_status_check,
+    ADD CONSTRAINT users_status_check CHECK (status IN ('Active', 'Banned'));
@@ -0,0 +1,24 @@
+-- Deleted accounts are hidden straight away and purged once the grace period
+-- is over
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ NULL,
+    DROP CONSTRAINT IF EXISTS users_status_check,
+    ADD CONSTRAINT users_status_check CHECK (status IN ('Active', 'Banned', 'Deleted'));
+
+CREATE INDEX IF NOT EXISTS idx_users_deleted_at ON users(deleted_at) WHERE deleted_at IS NOT NULL;
+
+-- Archives of a user's data, built in the background and kept until they expire
+CREATE TABLE IF NOT EXISTS data_exports (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status VARCHAR(20) NOT NULL DEFAULT 'Pending',
+    storage_key VARCHAR(256) NULL,
+    size_bytes BIGINT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMPTZ NULL,
+    expires_at TIMESTAMPTZ NULL,
+    CONSTRAINT data_exports_status_check CHECK (status IN ('Pending', 'Ready', 'Failed'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_data_exports_user_created ON data_exports(user_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_data_exports_expires_at ON data_exports(expires_at) WHERE storage_key IS NOT NULL;
@@ -0,0 +1,7 @@
+-- Preferences above the old bound can't stay under the old check
+UPDATE user_preferences SET max_age = 50 WHERE max_age > 50;
+UPDATE user_preferences SET min_age = 50 WHERE min_age > 50;
+
+ALTER TABLE user_preferences
+    DROP CONSTRAINT IF EXISTS preferences_age_check,
+    ADD CONSTRAINT preferences_age_check CHECK (min_age >= 18 AND max_age <= 50 AND min_age <= max_age);
@@ -0,0 +1,5 @@
+-- The upper age bound is a setting (MAXIMUM_AGE), only the legal minimum
+-- stays in the schema
+ALTER TABLE user_preferences
+    DROP CONSTRAINT IF EXISTS preferences_age_check,
+    ADD CONSTRAINT preferences_age_check CHECK (min_age >= 18 AND min_age <= max_age);
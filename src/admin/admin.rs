@@ -0,0 +1,640 @@
+use actix_web::{
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{prelude::FromRow, PgPool};
+use uuid::Uuid;
+
+use super::audit::{self, AuditAction, AuditEntry};
+use crate::{
+    auth::{
+        session::{list_sessions, revoke_all_sessions},
+        AccountStatus, AuthenticatedUser, Role,
+    },
+    cache::Cache,
+    common::{AppError, ResponseToSend},
+    config::Settings,
+    moderation::moderation::{suspend_user, Suspension},
+    photos::{Photo, Photos},
+    preferences::Preferences,
+    storage::{BlobStore, PrivateBlobStore},
+    user::{deletion, User},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+// Account fields only staff get to see
+#[derive(Serialize, FromRow, Debug)]
+pub struct Account {
+    id: Uuid,
+    username: String,
+    email: String,
+    role: Role,
+    status: AccountStatus,
+    email_verified_at: Option<DateTime<Utc>>,
+    suspended_until: Option<DateTime<Utc>>,
+    suspension_reason: Option<String>,
+    banned_at: Option<DateTime<Utc>>,
+    ban_reason: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow, Serialize, Debug)]
+pub struct AccountActivity {
+    reports_against: i64,
+    reports_filed: i64,
+    blocked_by: i64,
+    matches: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AccountDetails {
+    #[serde(flatten)]
+    account: Account,
+    profile: Option<User>,
+    photos: Vec<Photo>,
+    preferences: Preferences,
+    activity: AccountActivity,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserSearch {
+    // Matched against username and email, or an exact user id
+    q: Option<String>,
+    page: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Ban {
+    reason: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RoleChange {
+    role: Role,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuditQuery {
+    actor_id: Option<Uuid>,
+    target_user_id: Option<Uuid>,
+    page: Option<i64>,
+    limit: Option<i64>,
+}
+
+pub struct Admin;
+
+impl Admin {
+    // Search Users
+    pub async fn search_users(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        query: Query<UserSearch>,
+    ) -> Result<HttpResponse, AppError> {
+        let search = query.q.as_deref().map(str::trim).unwrap_or_default();
+        let (page, limit, offset) = page_and_limit(query.page, query.limit)?;
+
+        let accounts = sqlx::query_as::<_, Account>(
+            "SELECT id, username, email, role, status, email_verified_at, suspended_until, suspension_reason, banned_at, ban_reason,
+                    created_at, updated_at
+             FROM users
+             WHERE $1 = '' OR username ILIKE '%' || $1 || '%' OR email ILIKE '%' || $1 || '%' OR id::TEXT = $1
+             ORDER BY created_at DESC
+             LIMIT $2 OFFSET $3",
+        )
+        .bind(search)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&**db)
+        .await?;
+
+        let details = json!({ "q": search, "page": page });
+        audit::record(
+            &**db,
+            auth_user.user_id,
+            AuditAction::SearchUsers,
+            None,
+            details,
+        )
+        .await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Users Fetch Successfully".to_string(),
+            data: Some(accounts),
+        }))
+    }
+
+    // Get User, with everything about the account
+    pub async fn get_user(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        settings: Data<Settings>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = path.into_inner();
+
+        let Some(account) = find_account(&db, user_id).await? else {
+            return Err(AppError::NotFound("User Not Found".to_string()));
+        };
+
+        let photos = Photos::list(&db, user_id).await?;
+        let preferences = Preferences::for_user(&db, &cache, &settings.profile, user_id).await?;
+        let activity = sqlx::query_as::<_, AccountActivity>(
+            "SELECT (SELECT COUNT(*) FROM reports WHERE reported_id = $1) AS reports_against,
+                    (SELECT COUNT(*) FROM reports WHERE reporter_id = $1) AS reports_filed,
+                    (SELECT COUNT(*) FROM blocks WHERE blocked_id = $1) AS blocked_by,
+                    (SELECT COUNT(*) FROM matches WHERE user_one_id = $1 OR user_two_id = $1) AS matches",
+        )
+        .bind(user_id)
+        .fetch_one(&**db)
+        .await?;
+        // Accounts without profile data yet have no profile
+        let profile = User::get_user_basic_data(&db, &cache, user_id).await?;
+
+        audit::record(
+            &**db,
+            auth_user.user_id,
+            AuditAction::ViewUser,
+            Some(user_id),
+            json!({}),
+        )
+        .await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "User Fetch Successfully".to_string(),
+            data: Some(AccountDetails {
+                account,
+                profile,
+                photos,
+                preferences,
+                activity,
+            }),
+        }))
+    }
+
+    // Get User Sessions
+    pub async fn get_sessions(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = path.into_inner();
+
+        if find_account(&db, user_id).await?.is_none() {
+            return Err(AppError::NotFound("User Not Found".to_string()));
+        }
+
+        let sessions = list_sessions(&mut redis.get_ref().clone(), user_id).await?;
+
+        audit::record(
+            &**db,
+            auth_user.user_id,
+            AuditAction::ViewSessions,
+            Some(user_id),
+            json!({}),
+        )
+        .await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Sessions Fetch Successfully".to_string(),
+            data: Some(sessions),
+        }))
+    }
+
+    // Suspend User
+    pub async fn suspend_user(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+        suspension: Json<Suspension>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = path.into_inner();
+
+        let reason = suspension.validate().map_err(AppError::BadRequest)?;
+        check_target(&db, &auth_user, user_id).await?;
+
+        Self::suspend(&db, auth_user.user_id, user_id, suspension.days, reason).await?;
+
+        // Signs the user out everywhere
+        revoke_all_sessions(&mut redis.get_ref().clone(), user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "User Suspended Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    async fn suspend(
+        db: &PgPool,
+        actor_id: Uuid,
+        user_id: Uuid,
+        days: i64,
+        reason: &str,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = db.begin().await?;
+        suspend_user(&mut tx, user_id, days, reason).await?;
+        let details = json!({ "days": days, "reason": reason });
+        audit::record(
+            &mut *tx,
+            actor_id,
+            AuditAction::SuspendUser,
+            Some(user_id),
+            details,
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    // Unsuspend User
+    pub async fn unsuspend_user(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = path.into_inner();
+
+        check_target(&db, &auth_user, user_id).await?;
+
+        Self::unsuspend(&db, auth_user.user_id, user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "User Unsuspended Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    async fn unsuspend(db: &PgPool, actor_id: Uuid, user_id: Uuid) -> Result<(), sqlx::Error> {
+        let mut tx = db.begin().await?;
+        sqlx::query(
+            "UPDATE users SET suspended_until = NULL, suspension_reason = NULL, updated_at = CURRENT_TIMESTAMP WHERE id = $1",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        audit::record(
+            &mut *tx,
+            actor_id,
+            AuditAction::UnsuspendUser,
+            Some(user_id),
+            json!({}),
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    // Ban User until an admin lifts it, admins only
+    pub async fn ban_user(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+        body: Json<Ban>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = path.into_inner();
+
+        if !auth_user.has_role(Role::Admin) {
+            return Err(AppError::Forbidden("Insufficient Permissions".to_string()));
+        }
+        let reason = body.reason.trim();
+        if reason.is_empty() {
+            return Err(AppError::BadRequest("Reason is required".to_string()));
+        }
+        check_target(&db, &auth_user, user_id).await?;
+
+        Self::ban(&db, auth_user.user_id, user_id, reason).await?;
+
+        // Signs the user out everywhere
+        revoke_all_sessions(&mut redis.get_ref().clone(), user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "User Banned Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    async fn ban(
+        db: &PgPool,
+        actor_id: Uuid,
+        user_id: Uuid,
+        reason: &str,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = db.begin().await?;
+        sqlx::query(
+            "UPDATE users SET status = $2, banned_at = CURRENT_TIMESTAMP, ban_reason = $3, updated_at = CURRENT_TIMESTAMP
+             WHERE id = $1 AND status <> 'Deleted'",
+        )
+        .bind(user_id)
+        .bind(AccountStatus::Banned)
+        .bind(reason)
+        .execute(&mut *tx)
+        .await?;
+        audit::record(
+            &mut *tx,
+            actor_id,
+            AuditAction::BanUser,
+            Some(user_id),
+            json!({ "reason": reason }),
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    // Unban User, admins only. A suspension still running stays in place.
+    pub async fn unban_user(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = path.into_inner();
+
+        if !auth_user.has_role(Role::Admin) {
+            return Err(AppError::Forbidden("Insufficient Permissions".to_string()));
+        }
+        check_target(&db, &auth_user, user_id).await?;
+
+        Self::unban(&db, auth_user.user_id, user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "User Unbanned Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    async fn unban(db: &PgPool, actor_id: Uuid, user_id: Uuid) -> Result<(), sqlx::Error> {
+        let mut tx = db.begin().await?;
+        sqlx::query(
+            "UPDATE users SET status = $2, banned_at = NULL, ban_reason = NULL, updated_at = CURRENT_TIMESTAMP
+             WHERE id = $1 AND status = 'Banned'",
+        )
+        .bind(user_id)
+        .bind(AccountStatus::Active)
+        .execute(&mut *tx)
+        .await?;
+        audit::record(
+            &mut *tx,
+            actor_id,
+            AuditAction::UnbanUser,
+            Some(user_id),
+            json!({}),
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    // Delete User, admins only
+    pub async fn delete_user(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        cache: Data<Cache>,
+        blob_store: Data<dyn BlobStore>,
+        private_store: Data<PrivateBlobStore>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = path.into_inner();
+
+        if !auth_user.has_role(Role::Admin) {
+            return Err(AppError::Forbidden("Insufficient Permissions".to_string()));
+        }
+        let account = check_target(&db, &auth_user, user_id).await?;
+
+        // Collected first, the rows go with the account
+        let storage_keys = deletion::storage_keys(&db, user_id).await?;
+
+        Self::delete(&db, auth_user.user_id, &account).await?;
+
+        if let Err(e) = revoke_all_sessions(&mut redis.get_ref().clone(), user_id).await {
+            println!("Failed to revoke sessions of deleted user: {}", e);
+        }
+        deletion::evict_caches(&cache, &redis, user_id).await;
+        deletion::delete_blobs(&**blob_store, &private_store, &storage_keys).await;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "User Deleted Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    async fn delete(db: &PgPool, actor_id: Uuid, account: &Account) -> Result<(), sqlx::Error> {
+        let mut tx = db.begin().await?;
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(account.id)
+            .execute(&mut *tx)
+            .await?;
+        // The account is gone, keep who it was
+        let details = json!({ "username": account.username, "email": account.email });
+        audit::record(
+            &mut *tx,
+            actor_id,
+            AuditAction::DeleteUser,
+            Some(account.id),
+            details,
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    // Verify Email on the user's behalf, admins only
+    pub async fn verify_email(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = path.into_inner();
+
+        if !auth_user.has_role(Role::Admin) {
+            return Err(AppError::Forbidden("Insufficient Permissions".to_string()));
+        }
+
+        if !Self::mark_verified(&db, auth_user.user_id, user_id).await? {
+            return Err(AppError::NotFound("User Not Found".to_string()));
+        }
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "Email Verified Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    async fn mark_verified(
+        db: &PgPool,
+        actor_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = db.begin().await?;
+        let updated = sqlx::query(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP), updated_at = CURRENT_TIMESTAMP WHERE id = $1",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Ok(false);
+        }
+        audit::record(
+            &mut *tx,
+            actor_id,
+            AuditAction::VerifyEmail,
+            Some(user_id),
+            json!({}),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    // Change Role, admins only
+    pub async fn change_role(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+        body: Json<RoleChange>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = path.into_inner();
+
+        if !auth_user.has_role(Role::Admin) {
+            return Err(AppError::Forbidden("Insufficient Permissions".to_string()));
+        }
+        let account = check_target(&db, &auth_user, user_id).await?;
+
+        Self::set_role(&db, auth_user.user_id, &account, body.role).await?;
+
+        // Tokens carry the role, sign the user out so the new one applies
+        revoke_all_sessions(&mut redis.get_ref().clone(), user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "Role Changed Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    async fn set_role(
+        db: &PgPool,
+        actor_id: Uuid,
+        account: &Account,
+        role: Role,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = db.begin().await?;
+        sqlx::query("UPDATE users SET role = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2")
+            .bind(role)
+            .bind(account.id)
+            .execute(&mut *tx)
+            .await?;
+        let details = json!({ "from": account.role, "to": role });
+        audit::record(
+            &mut *tx,
+            actor_id,
+            AuditAction::ChangeRole,
+            Some(account.id),
+            details,
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    // Get Audit Log, newest first, admins only
+    pub async fn get_audit_log(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        query: Query<AuditQuery>,
+    ) -> Result<HttpResponse, AppError> {
+        if !auth_user.has_role(Role::Admin) {
+            return Err(AppError::Forbidden("Insufficient Permissions".to_string()));
+        }
+        let (page, limit, offset) = page_and_limit(query.page, query.limit)?;
+
+        let entries = sqlx::query_as::<_, AuditEntry>(
+            "SELECT a.id, a.actor_id, u.username AS actor_username, a.action, a.target_user_id, a.details, a.created_at
+             FROM audit_log a
+             LEFT JOIN users u ON u.id = a.actor_id
+             WHERE ($1::UUID IS NULL OR a.actor_id = $1)
+               AND ($2::UUID IS NULL OR a.target_user_id = $2)
+             ORDER BY a.created_at DESC, a.id
+             LIMIT $3 OFFSET $4",
+        )
+        .bind(query.actor_id)
+        .bind(query.target_user_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&**db)
+        .await?;
+
+        let details = json!({ "actor_id": query.actor_id, "page": page });
+        audit::record(
+            &**db,
+            auth_user.user_id,
+            AuditAction::ViewAuditLog,
+            query.target_user_id,
+            details,
+        )
+        .await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Audit Log Fetch Successfully".to_string(),
+            data: Some(entries),
+        }))
+    }
+}
+
+async fn find_account(db: &PgPool, user_id: Uuid) -> Result<Option<Account>, sqlx::Error> {
+    sqlx::query_as::<_, Account>(
+        "SELECT id, username, email, role, status, email_verified_at, suspended_until, suspension_reason, banned_at, ban_reason,
+                    created_at, updated_at
+         FROM users WHERE id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await
+}
+
+// The account staff are about to act on, if they are allowed to. Nobody acts
+// on their own account through the admin API.
+async fn check_target(
+    db: &PgPool,
+    actor: &AuthenticatedUser,
+    user_id: Uuid,
+) -> Result<Account, AppError> {
+    if actor.user_id == user_id {
+        return Err(AppError::Forbidden(
+            "You can't do this to your own account".to_string(),
+        ));
+    }
+
+    match find_account(db, user_id).await? {
+        Some(account) if actor.claims.role.outranks(account.role) => Ok(account),
+        Some(_) => Err(AppError::Forbidden("Insufficient Permissions".to_string())),
+        None => Err(AppError::NotFound("User Not Found".to_string())),
+    }
+}
+
+// Page, limit and the offset of the page's first row
+fn page_and_limit(page: Option<i64>, limit: Option<i64>) -> Result<(i64, i64, i64), AppError> {
+    let page = page.unwrap_or(1).max(1);
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = (page - 1)
+        .checked_mul(limit)
+        .ok_or_else(|| AppError::BadRequest("Page Out Of Range".to_string()))?;
+    Ok((page, limit, offset))
+}
@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgExecutor};
+use strum_macros::Display;
+use uuid::Uuid;
+
+#[derive(sqlx::Type, Debug, Deserialize, Display, Serialize, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum AuditAction {
+    SearchUsers,
+    ViewUser,
+    ViewSessions,
+    SuspendUser,
+    UnsuspendUser,
+    BanUser,
+    UnbanUser,
+    DeleteUser,
+    VerifyEmail,
+    ChangeRole,
+    ViewReports,
+    UpdateReport,
+    ViewAuditLog,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct AuditEntry {
+    id: Uuid,
+    actor_id: Option<Uuid>,
+    actor_username: Option<String>,
+    action: AuditAction,
+    target_user_id: Option<Uuid>,
+    details: sqlx::types::Json<serde_json::Value>,
+    created_at: DateTime<Utc>,
+}
+
+// Write an audit log entry. Actions that change something record it in the
+// same transaction, so there is no change without its entry.
+pub async fn record<'e, E: PgExecutor<'e>>(
+    executor: E,
+    actor_id: Uuid,
+    action: AuditAction,
+    target_user_id: Option<Uuid>,
+    details: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO audit_log (id, actor_id, action, target_user_id, details) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(actor_id)
+    .bind(action)
+    .bind(target_user_id)
+    .bind(details)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
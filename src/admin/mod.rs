@@ -0,0 +1,3 @@
+pub mod admin;
+pub mod audit;
+pub use admin::Admin;
@@ -0,0 +1,130 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use strum_macros::Display;
+
+// A suspension isn't a status of its own, it lasts as long as
+// `suspended_until` is in the future
+#[derive(
+    sqlx::Type, Debug, Default, Deserialize, Display, Serialize, Clone, Copy, PartialEq, Eq,
+)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    Banned,
+    // Waiting to be purged, see `user::deletion`
+    Deleted,
+}
+
+// The columns deciding whether an account may be used. Select
+// `status, ban_reason, suspended_until, suspension_reason` to load it.
+#[derive(FromRow, Debug, Default)]
+pub struct AccountState {
+    pub status: AccountStatus,
+    pub ban_reason: Option<String>,
+    pub suspended_until: Option<DateTime<Utc>>,
+    pub suspension_reason: Option<String>,
+}
+
+impl AccountState {
+    // Why the account can't be used right now, if it can't
+    pub fn restriction(&self) -> Option<String> {
+        match self.status {
+            AccountStatus::Banned => {
+                return Some(with_reason("Account Banned".to_string(), &self.ban_reason))
+            }
+            AccountStatus::Deleted => return Some("Account Deleted".to_string()),
+            AccountStatus::Active => {}
+        }
+
+        match self.suspended_until {
+            Some(until) if until > Utc::now() => Some(with_reason(
+                format!(
+                    "Account Suspended until {}",
+                    until.format("%Y-%m-%d %H:%M UTC")
+                ),
+                &self.suspension_reason,
+            )),
+            _ => None,
+        }
+    }
+}
+
+fn with_reason(message: String, reason: &Option<String>) -> String {
+    match reason {
+        Some(reason) => format!("{}: {}", message, reason),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn active_account_is_not_restricted() {
+        assert_eq!(AccountState::default().restriction(), None);
+    }
+
+    #[test]
+    fn banned_account_is_restricted_with_reason() {
+        let state = AccountState {
+            status: AccountStatus::Banned,
+            ban_reason: Some("Spam".to_string()),
+            ..AccountState::default()
+        };
+        assert_eq!(state.restriction().as_deref(), Some("Account Banned: Spam"));
+
+        let state = AccountState {
+            status: AccountStatus::Banned,
+            ..AccountState::default()
+        };
+        assert_eq!(state.restriction().as_deref(), Some("Account Banned"));
+    }
+
+    #[test]
+    fn deleted_account_is_restricted() {
+        let state = AccountState {
+            status: AccountStatus::Deleted,
+            ..AccountState::default()
+        };
+        assert_eq!(state.restriction().as_deref(), Some("Account Deleted"));
+    }
+
+    #[test]
+    fn ban_wins_over_suspension() {
+        let state = AccountState {
+            status: AccountStatus::Banned,
+            suspended_until: Some(Utc::now() + Duration::days(1)),
+            suspension_reason: Some("Harassment".to_string()),
+            ..AccountState::default()
+        };
+        assert_eq!(state.restriction().as_deref(), Some("Account Banned"));
+    }
+
+    #[test]
+    fn suspension_lasts_until_it_ends() {
+        let until = Utc::now() + Duration::days(1);
+        let suspended = AccountState {
+            suspended_until: Some(until),
+            suspension_reason: Some("Harassment".to_string()),
+            ..AccountState::default()
+        };
+        assert_eq!(
+            suspended.restriction(),
+            Some(format!(
+                "Account Suspended until {}: Harassment",
+                until.format("%Y-%m-%d %H:%M UTC")
+            ))
+        );
+
+        let ended = AccountState {
+            suspended_until: Some(Utc::now() - Duration::minutes(1)),
+            ..AccountState::default()
+        };
+        assert_eq!(ended.restriction(), None);
+    }
+}
@@ -1,25 +1,44 @@
-use std::sync::Arc;
-
-use crate::common::{
-    handle_bad_request, handle_conflict_error, handle_internal_server_error, ResponseToSend,
+use crate::{
+    common::{AppError, ResponseToSend},
+    config::Settings,
+    mailer::{
+        send_with_retry,
+        templates::{otp_email, password_reset_email},
+        Mailer,
+    },
 };
 
 use super::{
+    account_status::AccountState,
+    extractor::AuthenticatedUser,
     jwt::generate_token,
+    keys::Keyring,
+    otp::{
+        clear_otp, generate_otp, otp_key, record_failed_attempt, reserve_send, store_otp,
+        OtpConfig, OtpError, SendLimits,
+    },
+    password_reset::{consume_reset_token, issue_reset_token},
+    roles::Role,
+    session::{
+        create_session, revoke_all_sessions, revoke_session, rotate_refresh_token, RefreshOutcome,
+        SessionConfig,
+    },
     utils::{decrypt_password, encrypt_password},
 };
 use actix_web::{
     cookie::{time::Duration, Cookie, SameSite},
     web::{Data, Json},
-    HttpResponse, Responder,
+    HttpRequest, HttpResponse,
 };
-use rand::Rng;
-use redis::{aio::MultiplexedConnection, AsyncCommands};
+use chrono::{DateTime, Utc};
+use redis::{aio::ConnectionManager, AsyncCommands};
 use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, PgPool};
-use tokio::sync::Mutex;
 use uuid::Uuid;
 
+const ACCESS_TOKEN_COOKIE: &str = "auth_token";
+const REFRESH_TOKEN_COOKIE: &str = "refresh_token";
+
 #[derive(Serialize, Deserialize)]
 pub struct Register {
     username: String,
@@ -33,122 +52,344 @@ pub struct VerifyOtp {
     otp: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ResendOtp {
+    email: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForgotPassword {
+    email: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResetPassword {
+    token: String,
+    password: String,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Login {
     username: String,
     password: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct RefreshToken {
+    refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct TokenPair {
+    access_token: String,
+    refresh_token: String,
+    // Seconds until the access token expires
+    expires_in: i64,
+}
+
 #[derive(Serialize, Deserialize, FromRow)]
 pub struct User {
     id: uuid::Uuid,
     password: String,
+    email_verified_at: Option<DateTime<Utc>>,
+    role: Role,
+    #[sqlx(flatten)]
+    #[serde(skip)]
+    state: AccountState,
+}
+
+#[derive(FromRow)]
+struct TokenOwner {
+    role: Role,
+    #[sqlx(flatten)]
+    state: AccountState,
 }
 
 impl Register {
-    async fn check_user_existance(db: Data<PgPool>, username: &str) -> bool {
+    async fn check_user_existance(db: Data<PgPool>, username: &str) -> Result<bool, sqlx::Error> {
         sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE username = $1)")
             .bind(username)
             .fetch_one(&**db)
             .await
-            // .map_err(|_| HttpResponse::InternalServerError().finish())?
-            .unwrap_or(false)
     }
 
     pub async fn register_user(
         db: Data<PgPool>,
-        redis: Data<Arc<Mutex<MultiplexedConnection>>>,
+        redis: Data<ConnectionManager>,
+        mailer: Data<dyn Mailer>,
+        settings: Data<Settings>,
         user: Json<Register>,
-    ) -> impl Responder {
-        let is_user_exists = Self::check_user_existance(db.clone(), &user.username).await;
+    ) -> Result<HttpResponse, AppError> {
+        let is_user_exists = Self::check_user_existance(db.clone(), &user.username).await?;
 
         if is_user_exists {
-            return handle_conflict_error("User Already Exists");
+            return Err(AppError::Conflict("User Already Exists".to_string()));
         }
 
         let user_id = Uuid::new_v4();
 
         let hash_password = encrypt_password(&user.password);
 
-        let otp = Register::get_otp().to_string();
-        println!("{}", otp);
+        // Store user in db
+        sqlx::query("INSERT INTO users (id, username, email, password) VALUES ($1, $2, $3, $4)")
+            .bind(user_id)
+            .bind(user.username.clone())
+            .bind(user.email.clone())
+            .bind(hash_password)
+            .execute(&**db)
+            .await?;
 
-        // Store OTP in Redis with an expiration of 30 seconds
-        let redis_key = format!("otp:{}", user.email); // Use a unique key
-                                                       // Lock the Redis connection before using it
-        let mut redis_conn = redis.lock().await;
-        let redis_key_set = redis_conn
-            .set_ex::<&str, &str, ()>(&redis_key, &otp, 30)
-            .await;
+        // Only report success once the mailer has accepted the message
+        if let Err(e) =
+            Self::send_otp(&redis, &**mailer, &settings, &user.email, &user.username).await
+        {
+            Self::remove_unverified_user(db.clone(), user_id).await;
+            return Err(e);
+        }
 
-        // Store otp in redis cache for 30 sec
-        match redis_key_set {
-            Ok(_) => {
-                // TODO: Send mail to user with otp
-
-                // Store user in db
-                let user = sqlx::query(
-                    "INSERT INTO users (id, username, email, password) VALUES ($1, $2, $3, $4)",
-                )
-                .bind(user_id)
-                .bind(user.username.clone())
-                .bind(user.email.clone())
-                .bind(hash_password)
-                .execute(&**db)
-                .await;
-
-                match user {
-                    Ok(_) => HttpResponse::Created().json(ResponseToSend::<()> {
-                        success: true,
-                        message: "Email Sent Successfully".to_string(),
-                        data: None,
-                    }),
-                    Err(e) => handle_internal_server_error(&e.to_string()),
-                }
-            }
-            Err(_) => {
-                return handle_bad_request("Failed to generate OTP");
+        Ok(HttpResponse::Created().json(ResponseToSend::<()> {
+            success: true,
+            message: "Email Sent Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    // Resend OTP
+    pub async fn resend_otp(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        mailer: Data<dyn Mailer>,
+        settings: Data<Settings>,
+        body: Json<ResendOtp>,
+    ) -> Result<HttpResponse, AppError> {
+        let user = sqlx::query_as::<_, (String, Option<DateTime<Utc>>)>(
+            "SELECT username, email_verified_at FROM users WHERE email = $1",
+        )
+        .bind(&body.email)
+        .fetch_optional(&**db)
+        .await?;
+
+        match user {
+            Some((_, Some(_))) => Err(AppError::Conflict("Email Already Verified".to_string())),
+            Some((username, None)) => {
+                Self::send_otp(&redis, &**mailer, &settings, &body.email, &username).await?;
+                Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+                    success: true,
+                    message: "Email Sent Successfully".to_string(),
+                    data: None,
+                }))
             }
+            None => Err(AppError::NotFound("User Not Found".to_string())),
+        }
+    }
+
+    // Enforce the per-email cooldown and daily cap of one kind of email
+    async fn reserve_mail_send(
+        redis_conn: &mut ConnectionManager,
+        limits: &SendLimits,
+        email: &str,
+    ) -> Result<(), AppError> {
+        match reserve_send(redis_conn, limits, email).await {
+            Ok(_) => Ok(()),
+            Err(OtpError::Cooldown(seconds)) => Err(AppError::TooManyRequests {
+                code: limits.cooldown_code,
+                message: format!(
+                    "Please wait {} seconds before requesting another email",
+                    seconds
+                ),
+                retry_after: Some(seconds),
+            }),
+            Err(OtpError::DailyLimitReached(seconds)) => Err(AppError::TooManyRequests {
+                code: limits.daily_limit_code,
+                message: "Daily email limit reached, try again tomorrow".to_string(),
+                retry_after: Some(seconds),
+            }),
+            Err(OtpError::Redis(e)) => Err(e.into()),
+        }
+    }
+
+    // Generate an OTP, store it in Redis and email it, respecting the send limits
+    async fn send_otp(
+        redis: &ConnectionManager,
+        mailer: &dyn Mailer,
+        settings: &Settings,
+        email: &str,
+        username: &str,
+    ) -> Result<(), AppError> {
+        let otp_config = &settings.otp;
+        let otp = generate_otp();
+
+        let mut redis_conn = redis.clone();
+        Self::reserve_mail_send(&mut redis_conn, &otp_config.send_limits(), email).await?;
+        store_otp(&mut redis_conn, otp_config, email, &otp).await?;
+
+        let email_message = otp_email(email, username, &otp, otp_config.ttl_seconds);
+        if let Err(e) = send_with_retry(mailer, &email_message, settings.mail.max_attempts).await {
+            let _ = clear_otp(&mut redis_conn, email).await;
+            return Err(AppError::Internal(format!(
+                "Failed to send OTP email to {}: {}",
+                email, e
+            )));
         }
+
+        Ok(())
+    }
+
+    // Roll back a registration whose OTP could not be delivered so the
+    // user is able to sign up again with the same username
+    async fn remove_unverified_user(db: Data<PgPool>, user_id: Uuid) {
+        let _ = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user_id)
+            .execute(&**db)
+            .await;
     }
 
     // Verify OTP
     pub async fn verify_otp(
-        redis: Data<Arc<Mutex<MultiplexedConnection>>>,
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        otp_config: Data<OtpConfig>,
         verify_otp_dto: Json<VerifyOtp>,
-    ) -> impl Responder {
-        let mut redis_conn = redis.lock().await;
-        let redis_key = format!("otp:{}", verify_otp_dto.email); // Use a unique key
-        let get_otp: Result<Option<String>, redis::RedisError> =
-            redis_conn.get(redis_key.clone()).await;
-
-        match get_otp {
-            Ok(Some(stored_otp)) => {
-                if stored_otp == verify_otp_dto.otp {
-                    // OTP is correct, delete the OTP key after successful verification
-                    let delete_key: Result<i64, redis::RedisError> =
-                        redis_conn.del(redis_key.clone()).await;
-                    match delete_key {
-                        Ok(deleted) if deleted > 0 => {
-                            return HttpResponse::Ok().json(ResponseToSend::<()> {
-                                success: true,
-                                message: "OTP verified successfully".to_string(),
-                                data: None,
-                            });
-                        }
-                        Ok(_) => handle_internal_server_error("Failed to delete OTP from Redis"),
-                        Err(_) => handle_bad_request("Invalid OTP"),
-                    }
-                } else {
-                    handle_bad_request("Invalid OTP")
-                }
-            }
-            Ok(None) => handle_bad_request("OTP not found or expired"),
-            Err(e) => {
-                // println!("{}", e);
-                handle_internal_server_error(&e.to_string())
+    ) -> Result<HttpResponse, AppError> {
+        let mut redis_conn = redis.get_ref().clone();
+        let redis_key = otp_key(&verify_otp_dto.email); // Use a unique key
+        let stored_otp: Option<String> = redis_conn.get(redis_key.clone()).await?;
+
+        let Some(stored_otp) = stored_otp else {
+            return Err(AppError::BadRequest("OTP not found or expired".to_string()));
+        };
+
+        if stored_otp != verify_otp_dto.otp {
+            let attempts =
+                record_failed_attempt(&mut redis_conn, &otp_config, &verify_otp_dto.email).await?;
+
+            // Too many wrong guesses, the OTP can no longer be used
+            if attempts >= otp_config.max_attempts {
+                let _ = clear_otp(&mut redis_conn, &verify_otp_dto.email).await;
+                return Err(AppError::TooManyRequests {
+                    code: "otp_locked",
+                    message: "Too many failed attempts, request a new OTP".to_string(),
+                    retry_after: None,
+                });
             }
+
+            return Err(AppError::BadRequest("Invalid OTP".to_string()));
         }
+
+        // OTP is correct, persist the verification before consuming the OTP
+        let verified = sqlx::query(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP), updated_at = CURRENT_TIMESTAMP WHERE email = $1",
+        )
+        .bind(&verify_otp_dto.email)
+        .execute(&**db)
+        .await?;
+
+        if verified.rows_affected() == 0 {
+            return Err(AppError::NotFound("User Not Found".to_string()));
+        }
+
+        // Delete the OTP key after successful verification
+        if clear_otp(&mut redis_conn, &verify_otp_dto.email).await? == 0 {
+            return Err(AppError::Internal(
+                "Failed to delete OTP from Redis".to_string(),
+            ));
+        }
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "OTP verified successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    // Forgot Password
+    pub async fn forgot_password(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        mailer: Data<dyn Mailer>,
+        settings: Data<Settings>,
+        body: Json<ForgotPassword>,
+    ) -> Result<HttpResponse, AppError> {
+        // Limits apply per email whether or not it is registered, so the
+        // response never reveals which addresses have an account
+        Self::reserve_mail_send(
+            &mut redis.get_ref().clone(),
+            &settings.auth.password_reset_limits(),
+            &body.email,
+        )
+        .await?;
+
+        let user = sqlx::query_as::<_, (Uuid, String)>(
+            "SELECT id, username FROM users WHERE email = $1 AND email_verified_at IS NOT NULL",
+        )
+        .bind(&body.email)
+        .fetch_optional(&**db)
+        .await?;
+
+        let Some((user_id, username)) = user else {
+            return Ok(Self::reset_email_sent());
+        };
+
+        let ttl_seconds = settings.auth.password_reset_ttl_seconds;
+        let token = issue_reset_token(&mut redis.get_ref().clone(), user_id, ttl_seconds).await?;
+
+        let email = password_reset_email(&body.email, &username, &token, ttl_seconds);
+        if let Err(e) = send_with_retry(&**mailer, &email, settings.mail.max_attempts).await {
+            return Err(AppError::Internal(format!(
+                "Failed to send password reset email to {}: {}",
+                body.email, e
+            )));
+        }
+
+        Ok(Self::reset_email_sent())
+    }
+
+    fn reset_email_sent() -> HttpResponse {
+        HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "If the email is registered, a reset code has been sent".to_string(),
+            data: None,
+        })
+    }
+
+    // Reset Password
+    pub async fn reset_password(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        body: Json<ResetPassword>,
+    ) -> Result<HttpResponse, AppError> {
+        if body.password.is_empty() {
+            return Err(AppError::BadRequest(
+                "Password must not be empty".to_string(),
+            ));
+        }
+
+        let user_id = consume_reset_token(&mut redis.get_ref().clone(), &body.token)
+            .await?
+            .ok_or_else(|| AppError::BadRequest("Invalid or expired reset token".to_string()))?;
+
+        let hash_password = encrypt_password(&body.password);
+
+        let updated = sqlx::query(
+            "UPDATE users SET password = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2",
+        )
+        .bind(hash_password)
+        .bind(user_id)
+        .execute(&**db)
+        .await?;
+
+        if updated.rows_affected() == 0 {
+            return Err(AppError::NotFound("User Not Found".to_string()));
+        }
+
+        // Sign the user out everywhere now that the password changed
+        revoke_all_sessions(&mut redis.get_ref().clone(), user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "Password Reset Successfully".to_string(),
+            data: None,
+        }))
     }
 
     // Checks the type of variable
@@ -157,54 +398,201 @@ impl Register {
     // }
 
     // Login User
-    pub async fn login_user(db: Data<PgPool>, body: Json<Login>) -> impl Responder {
-        let response =
-            sqlx::query_as::<_, User>("SELECT id, password FROM users WHERE username = $1")
-                .bind(&body.username)
-                .fetch_one(&**db)
-                .await;
-
-        match response {
-            Ok(user) => {
-                let is_password_match = decrypt_password(&body.password.clone(), &user.password);
-
-                if !is_password_match {
-                    return HttpResponse::BadRequest().json(ResponseToSend::<()> {
-                        success: false,
-                        message: "Password Not Matched".to_string(),
-                        data: None,
-                    });
-                }
-
-                let token = generate_token(user.id);
-
-                let cookie = Cookie::build("auth_token", &token)
-                    .path("/")
-                    .http_only(true)
-                    .secure(true)
-                    .max_age(Duration::hours(24))
-                    .same_site(SameSite::Strict)
-                    .finish();
-
-                HttpResponse::Ok().cookie(cookie).json(ResponseToSend {
-                    success: true,
-                    message: "Signin Successfully".to_string(),
-                    data: Some(token),
-                })
+    pub async fn login_user(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        session_config: Data<SessionConfig>,
+        keyring: Data<Keyring>,
+        body: Json<Login>,
+    ) -> Result<HttpResponse, AppError> {
+        let user = sqlx::query_as::<_, User>(
+            "SELECT id, password, email_verified_at, role, status, ban_reason, suspended_until, suspension_reason
+             FROM users WHERE username = $1",
+        )
+        .bind(&body.username)
+        .fetch_optional(&**db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User Not Found".to_string()))?;
+
+        let is_password_match = decrypt_password(&body.password.clone(), &user.password);
+
+        if !is_password_match {
+            return Err(AppError::BadRequest("Password Not Matched".to_string()));
+        }
+
+        if user.email_verified_at.is_none() {
+            return Err(AppError::EmailNotVerified);
+        }
+
+        if let Some(restriction) = user.state.restriction() {
+            return Err(AppError::Forbidden(restriction));
+        }
+
+        let (session_id, refresh_token) =
+            create_session(&mut redis.get_ref().clone(), &session_config, user.id).await?;
+
+        Ok(Self::token_response(
+            &keyring,
+            &session_config,
+            user.id,
+            session_id,
+            user.role,
+            refresh_token,
+            "Signin Successfully",
+        ))
+    }
+
+    // Refresh Token
+    pub async fn refresh_token(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        session_config: Data<SessionConfig>,
+        keyring: Data<Keyring>,
+        req: HttpRequest,
+        body: Option<Json<RefreshToken>>,
+    ) -> Result<HttpResponse, AppError> {
+        // Mobile clients send the token in the body, browsers in a cookie
+        let refresh_token = body
+            .map(|body| body.into_inner().refresh_token)
+            .or_else(|| {
+                req.cookie(REFRESH_TOKEN_COOKIE)
+                    .map(|cookie| cookie.value().to_string())
+            });
+
+        let Some(refresh_token) = refresh_token else {
+            return Err(AppError::Unauthorized("Missing refresh token".to_string()));
+        };
+
+        let outcome = rotate_refresh_token(
+            &mut redis.get_ref().clone(),
+            &session_config,
+            &refresh_token,
+        )
+        .await?;
+
+        let (user_id, session_id, refresh_token) = match outcome {
+            RefreshOutcome::Rotated {
+                user_id,
+                session_id,
+                refresh_token,
+            } => (user_id, session_id, refresh_token),
+            RefreshOutcome::Invalid => {
+                return Err(AppError::Unauthorized("Invalid refresh token".to_string()))
             }
-            Err(e) => {
-                println!("{}", e);
-                HttpResponse::NotFound().json(ResponseToSend::<()> {
-                    success: false,
-                    message: e.to_string(),
-                    data: None,
-                })
+            RefreshOutcome::ReuseDetected => {
+                return Err(AppError::Unauthorized(
+                    "Refresh token reuse detected, session revoked".to_string(),
+                ))
             }
+        };
+
+        // Read the account again so a changed role or a ban applies from the
+        // next refresh
+        let owner = sqlx::query_as::<_, TokenOwner>(
+            "SELECT role, status, ban_reason, suspended_until, suspension_reason FROM users WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&**db)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("User Not Found".to_string()))?;
+
+        if let Some(restriction) = owner.state.restriction() {
+            return Err(AppError::Forbidden(restriction));
         }
+
+        Ok(Self::token_response(
+            &keyring,
+            &session_config,
+            user_id,
+            session_id,
+            owner.role,
+            refresh_token,
+            "Token Refreshed Successfully",
+        ))
+    }
+
+    // Logout from the current session
+    pub async fn logout(
+        redis: Data<ConnectionManager>,
+        auth_user: AuthenticatedUser,
+    ) -> Result<HttpResponse, AppError> {
+        revoke_session(&mut redis.get_ref().clone(), auth_user.claims.sid).await?;
+        Ok(Self::logged_out_response("Logout Successfully"))
+    }
+
+    // Logout from every session of the user
+    pub async fn logout_all(
+        redis: Data<ConnectionManager>,
+        auth_user: AuthenticatedUser,
+    ) -> Result<HttpResponse, AppError> {
+        revoke_all_sessions(&mut redis.get_ref().clone(), auth_user.user_id).await?;
+        Ok(Self::logged_out_response("Logged Out From All Sessions"))
     }
 
-    fn get_otp() -> u32 {
-        let mut rng = rand::thread_rng();
-        rng.gen_range(100000..999999)
+    // Issue an access token for the session and set both auth cookies
+    fn token_response(
+        keyring: &Keyring,
+        session_config: &SessionConfig,
+        user_id: Uuid,
+        session_id: Uuid,
+        role: Role,
+        refresh_token: String,
+        message: &str,
+    ) -> HttpResponse {
+        let access_token = generate_token(
+            keyring,
+            user_id,
+            session_id,
+            role,
+            session_config.access_token_ttl_minutes,
+        );
+
+        let access_cookie = Cookie::build(ACCESS_TOKEN_COOKIE, access_token.clone())
+            .path("/")
+            .http_only(true)
+            .secure(true)
+            .max_age(Duration::minutes(session_config.access_token_ttl_minutes))
+            .same_site(SameSite::Strict)
+            .finish();
+
+        // The refresh token is only ever sent to the auth endpoints
+        let refresh_cookie = Cookie::build(REFRESH_TOKEN_COOKIE, refresh_token.clone())
+            .path("/api/v1/auth")
+            .http_only(true)
+            .secure(true)
+            .max_age(Duration::days(session_config.refresh_token_ttl_days))
+            .same_site(SameSite::Strict)
+            .finish();
+
+        HttpResponse::Ok()
+            .cookie(access_cookie)
+            .cookie(refresh_cookie)
+            .json(ResponseToSend {
+                success: true,
+                message: message.to_string(),
+                data: Some(TokenPair {
+                    access_token,
+                    refresh_token,
+                    expires_in: session_config.access_token_ttl_minutes * 60,
+                }),
+            })
+    }
+
+    fn logged_out_response(message: &str) -> HttpResponse {
+        let mut access_cookie = Cookie::build(ACCESS_TOKEN_COOKIE, "").path("/").finish();
+        access_cookie.make_removal();
+        let mut refresh_cookie = Cookie::build(REFRESH_TOKEN_COOKIE, "")
+            .path("/api/v1/auth")
+            .finish();
+        refresh_cookie.make_removal();
+
+        HttpResponse::Ok()
+            .cookie(access_cookie)
+            .cookie(refresh_cookie)
+            .json(ResponseToSend::<()> {
+                success: true,
+                message: message.to_string(),
+                data: None,
+            })
     }
 }
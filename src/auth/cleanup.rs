@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+
+use crate::config::config::AuthSettings;
+
+// Periodically delete registrations whose email was never verified.
+// `unverified_account_ttl_hours` controls how long an unverified account is kept.
+pub fn spawn_unverified_user_cleanup(db: PgPool, settings: &AuthSettings) {
+    let ttl_hours = settings.unverified_account_ttl_hours;
+    let interval_minutes = settings.unverified_cleanup_interval_minutes;
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_minutes * 60));
+        loop {
+            interval.tick().await;
+
+            let deleted = sqlx::query(
+                "DELETE FROM users WHERE email_verified_at IS NULL AND created_at < NOW() - make_interval(hours => $1)",
+            )
+            .bind(ttl_hours)
+            .execute(&db)
+            .await;
+
+            match deleted {
+                Ok(result) if result.rows_affected() > 0 => {
+                    println!(
+                        "Removed {} unverified registrations",
+                        result.rows_affected()
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => println!("Failed to clean up unverified registrations: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,39 @@
+use std::{future::Future, pin::Pin};
+
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+use uuid::Uuid;
+
+use super::jwt::{validate_token, Claims};
+
+// The signed-in user. Declare it as a handler parameter to require a valid
+// access token; the request is rejected with 401/403 otherwise.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub claims: Claims,
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // Already validated by the `require_auth` middleware
+        if let Some(user) = req.extensions().get::<AuthenticatedUser>() {
+            let user = user.clone();
+            return Box::pin(async move { Ok(user) });
+        }
+
+        let req = req.clone();
+        Box::pin(async move { authenticate(&req).await })
+    }
+}
+
+// Validate the request's token and turn a rejection into an actix error
+pub async fn authenticate(req: &HttpRequest) -> Result<AuthenticatedUser, actix_web::Error> {
+    let claims = validate_token(req).await?;
+    Ok(AuthenticatedUser {
+        user_id: claims.sub,
+        claims,
+    })
+}
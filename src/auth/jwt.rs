@@ -1,76 +1,150 @@
 use actix_web::{
     cookie::time::{Duration, OffsetDateTime},
-    HttpRequest, HttpResponse,
+    http::header,
+    web::Data,
+    HttpRequest,
 };
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use redis::aio::ConnectionManager;
 use serde::{Deserialize, Serialize};
-use std::env;
+use sqlx::{prelude::FromRow, PgPool};
 
-use crate::common::ResponseToSend;
+use super::{
+    account_status::AccountState,
+    keys::{Keyring, ISSUER},
+    roles::Role,
+    session::is_session_active,
+};
+
+use crate::common::AppError;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Claims {
-    sub: uuid::Uuid,
-    exp: usize,
-    iat: usize,
-    iss: String,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+    pub sub: uuid::Uuid,
+    pub exp: usize,
+    pub iat: usize,
+    pub iss: String,
+    // Unique id of this token
+    pub jti: uuid::Uuid,
+    // Session the token belongs to, see `auth::session`
+    pub sid: uuid::Uuid,
+    // Tokens issued before roles existed belong to regular users
+    #[serde(default)]
+    pub role: Role,
 }
 
-pub fn generate_token(id: uuid::Uuid) -> String {
-    // Retrieve the secret key from environment variables
-    let secret_key =
-        env::var("COOKIES_SECRET_KEY").expect("COOKIES_SECRET_KEY must be set in the .env file");
-    // Calculate expiration time (current time + 2 hours)
-    let expiration = OffsetDateTime::now_utc() + Duration::hours(24);
-    let issuer = String::from("Amourithm");
+pub fn generate_token(
+    keyring: &Keyring,
+    id: uuid::Uuid,
+    session_id: uuid::Uuid,
+    role: Role,
+    ttl_minutes: i64,
+) -> String {
+    // Access tokens are short-lived, clients renew them with a refresh token
+    let expiration = OffsetDateTime::now_utc() + Duration::minutes(ttl_minutes);
+    let issuer = String::from(ISSUER);
     let claims = Claims {
         sub: id,
         exp: expiration.unix_timestamp() as usize,
         iat: OffsetDateTime::now_utc().unix_timestamp() as usize,
         iss: issuer,
+        jti: uuid::Uuid::new_v4(),
+        sid: session_id,
+        role,
     };
 
-    // Encode the Claims into a JWT token
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret_key.as_bytes()),
-    )
-    .expect("Error generating token")
+    // Sign the Claims with the keyring's active key
+    keyring.sign(&claims).expect("Error generating token")
 }
 
-// Middleware-like function to validate token and extract user data
-pub async fn validate_token(req: HttpRequest) -> Result<uuid::Uuid, HttpResponse> {
+// Read the access token from the `auth_token` cookie, or from an
+// `Authorization: Bearer` header for clients that don't keep cookies
+fn extract_token(req: &HttpRequest) -> Option<String> {
     if let Some(cookie) = req.cookie("auth_token") {
-        let token = cookie.value();
-        let secret_key = env::var("COOKIES_SECRET_KEY")
-            .expect("COOKIES_SECRET_KEY must be set in the .env file");
-        let verified_token = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(secret_key.as_bytes()), // Decode using the same secret key
-            &Validation::default(), // Use default validation (e.g., check expiry)
-        );
-
-        match verified_token {
-            Ok(data) => {
-                let user_id = data.claims.sub;
-                Ok(user_id) // Return user ID and username
-            }
-            Err(e) => {
-                // Handle invalid token
-                Err(HttpResponse::Unauthorized().json(ResponseToSend::<()> {
-                    success: false,
-                    message: format!("Invalid token: {}", e), // Serialize the error
-                    data: None,
-                }))
-            }
-        }
+        return Some(cookie.value().to_string());
+    }
+
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+// Validate the request's token and return its claims.
+// Handlers normally get these through the `AuthenticatedUser` extractor.
+pub async fn validate_token(req: &HttpRequest) -> Result<Claims, AppError> {
+    let token =
+        extract_token(req).ok_or_else(|| AppError::Unauthorized("Missing token".to_string()))?;
+    let keyring = req
+        .app_data::<Data<Keyring>>()
+        .ok_or_else(|| AppError::Internal("Signing Keys Not Configured".to_string()))?;
+
+    // Checks signature, expiry and issuer
+    let data = keyring
+        .verify::<Claims>(&token)
+        .map_err(|e| AppError::Unauthorized(format!("Invalid token: {}", e)))?;
+
+    let db = req
+        .app_data::<Data<PgPool>>()
+        .ok_or_else(|| AppError::Internal("Database Not Configured".to_string()))?;
+    let redis = req
+        .app_data::<Data<ConnectionManager>>()
+        .ok_or_else(|| AppError::Internal("Redis Not Configured".to_string()))?;
+
+    check_access(db, redis, data.claims.sub, data.claims.sid).await?;
+    Ok(data.claims)
+}
+
+// Whether the session and its owner may still be used. Checked on every
+// request, and periodically for connections that outlive one such as chat.
+pub async fn check_access(
+    db: &PgPool,
+    redis: &ConnectionManager,
+    user_id: uuid::Uuid,
+    session_id: uuid::Uuid,
+) -> Result<(), AppError> {
+    check_session_active(redis, user_id, session_id).await?;
+    check_account_state(db, user_id).await
+}
+
+#[derive(FromRow)]
+struct TokenOwner {
+    is_verified: bool,
+    #[sqlx(flatten)]
+    state: AccountState,
+}
+
+// Refuse tokens whose owner no longer exists, hasn't verified their email or
+// was banned or suspended after the token was issued
+async fn check_account_state(db: &PgPool, user_id: uuid::Uuid) -> Result<(), AppError> {
+    let owner = sqlx::query_as::<_, TokenOwner>(
+        "SELECT email_verified_at IS NOT NULL AS is_verified, status, ban_reason, suspended_until, suspension_reason
+         FROM users WHERE id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+
+    match owner {
+        Some(owner) if !owner.is_verified => Err(AppError::EmailNotVerified),
+        Some(owner) => match owner.state.restriction() {
+            Some(restriction) => Err(AppError::Forbidden(restriction)),
+            None => Ok(()),
+        },
+        None => Err(AppError::Unauthorized("User Not Found".to_string())),
+    }
+}
+
+// Refuse tokens whose session was ended by logout, password reset or reuse detection
+async fn check_session_active(
+    redis: &ConnectionManager,
+    user_id: uuid::Uuid,
+    session_id: uuid::Uuid,
+) -> Result<(), AppError> {
+    let mut redis_conn = redis.clone();
+    if is_session_active(&mut redis_conn, session_id, user_id).await? {
+        Ok(())
     } else {
-        // Handle missing token
-        Err(HttpResponse::Unauthorized().json(ResponseToSend::<()> {
-            success: false,
-            message: "Missing token".to_string(),
-            data: None,
-        }))
+        Err(AppError::Unauthorized("Session Revoked".to_string()))
     }
 }
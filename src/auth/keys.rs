@@ -0,0 +1,196 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use actix_web::{web::Data, HttpResponse, Responder};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey},
+    SigningKey, VerifyingKey,
+};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    errors::{Error, ErrorKind},
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+};
+use rand::rngs::OsRng;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::config::config::AuthSettings;
+
+pub const ISSUER: &str = "Amourithm";
+
+struct KeyEntry {
+    // Only present for keys we can still sign with
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+    // Base64url encoded public key, as published in the JWKS
+    x: String,
+}
+
+// Ed25519 keys used to sign and verify access tokens.
+//
+// Keys are read once at startup from JWT_KEYS_DIR, one PEM file per key named
+// `<kid>.pem`. A PKCS#8 private key can sign and verify, a public key can
+// only verify. JWT_ACTIVE_KID selects the signing key. To rotate, add the new
+// key, point JWT_ACTIVE_KID at it and keep the old key (private or public)
+// around until every token it signed has expired.
+pub struct Keyring {
+    active_kid: String,
+    keys: HashMap<String, KeyEntry>,
+}
+
+impl Keyring {
+    pub fn from_settings(settings: &AuthSettings) -> Result<Self, String> {
+        match &settings.jwt_keys_dir {
+            Some(dir) => Self::load_dir(Path::new(dir), settings.jwt_active_kid.clone()),
+            None => {
+                println!("JWT_KEYS_DIR is not set, signing tokens with an ephemeral key");
+                Ok(Self::ephemeral())
+            }
+        }
+    }
+
+    // A throwaway key for local development, tokens die with the process
+    fn ephemeral() -> Self {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let kid = format!("ephemeral-{}", uuid::Uuid::new_v4());
+        let entry = Self::private_entry(&signing_key).expect("Failed to encode ephemeral key");
+
+        Keyring {
+            active_kid: kid.clone(),
+            keys: HashMap::from([(kid, entry)]),
+        }
+    }
+
+    fn load_dir(dir: &Path, active_kid: Option<String>) -> Result<Self, String> {
+        let entries = fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read JWT_KEYS_DIR {}: {}", dir.display(), e))?;
+
+        let mut keys = HashMap::new();
+        let mut signing_kids = Vec::new();
+
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("pem") {
+                continue;
+            }
+            let Some(kid) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let pem = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+            let key_entry = if pem.contains("BEGIN PRIVATE KEY") {
+                let signing_key = SigningKey::from_pkcs8_pem(&pem).map_err(|e| {
+                    format!("Invalid Ed25519 private key {}: {}", path.display(), e)
+                })?;
+                signing_kids.push(kid.to_string());
+                Self::private_entry(&signing_key)?
+            } else {
+                let verifying_key = VerifyingKey::from_public_key_pem(&pem)
+                    .map_err(|e| format!("Invalid Ed25519 public key {}: {}", path.display(), e))?;
+                Self::public_entry(&verifying_key)?
+            };
+
+            keys.insert(kid.to_string(), key_entry);
+        }
+
+        let active_kid = match active_kid {
+            Some(kid) => kid,
+            None if signing_kids.len() == 1 => signing_kids.remove(0),
+            None => {
+                return Err(
+                    "JWT_ACTIVE_KID must be set when JWT_KEYS_DIR holds several private keys"
+                        .to_string(),
+                )
+            }
+        };
+
+        match keys.get(&active_kid) {
+            Some(entry) if entry.encoding.is_some() => Ok(Keyring { active_kid, keys }),
+            Some(_) => Err(format!(
+                "JWT key {} is public only and cannot sign",
+                active_kid
+            )),
+            None => Err(format!("JWT key {} not found in JWT_KEYS_DIR", active_kid)),
+        }
+    }
+
+    fn private_entry(signing_key: &SigningKey) -> Result<KeyEntry, String> {
+        let der = signing_key.to_pkcs8_der().map_err(|e| e.to_string())?;
+        let mut entry = Self::public_entry(&signing_key.verifying_key())?;
+        entry.encoding = Some(EncodingKey::from_ed_der(der.as_bytes()));
+        Ok(entry)
+    }
+
+    fn public_entry(verifying_key: &VerifyingKey) -> Result<KeyEntry, String> {
+        let x = URL_SAFE_NO_PAD.encode(verifying_key.to_bytes());
+        let decoding = DecodingKey::from_ed_components(&x).map_err(|e| e.to_string())?;
+        Ok(KeyEntry {
+            encoding: None,
+            decoding,
+            x,
+        })
+    }
+
+    // Sign the claims with the active key, tagging the header with its kid
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, Error> {
+        let entry = &self.keys[&self.active_kid];
+        let encoding = entry
+            .encoding
+            .as_ref()
+            .ok_or_else(|| Error::from(ErrorKind::InvalidKeyFormat))?;
+
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.active_kid.clone());
+        encode(&header, claims, encoding)
+    }
+
+    // Verify a token with whichever key its kid header names
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<TokenData<T>, Error> {
+        let header = decode_header(token)?;
+        let entry = header
+            .kid
+            .as_ref()
+            .and_then(|kid| self.keys.get(kid))
+            .ok_or_else(|| Error::from(ErrorKind::InvalidKeyFormat))?;
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&[ISSUER]);
+        decode::<T>(token, &entry.decoding, &validation)
+    }
+
+    // Public keys of every key in the ring, in JWKS format
+    pub fn jwks(&self) -> JwkSet {
+        let mut keys: Vec<Jwk> = self
+            .keys
+            .iter()
+            .map(|(kid, entry)| Jwk {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    key_algorithm: Some(KeyAlgorithm::EdDSA),
+                    key_id: Some(kid.clone()),
+                    ..Default::default()
+                },
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: entry.x.clone(),
+                }),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.common.key_id.cmp(&b.common.key_id));
+
+        JwkSet { keys }
+    }
+}
+
+// GET /.well-known/jwks.json, lets other services verify our tokens
+pub async fn jwks(keyring: Data<Keyring>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(keyring.jwks())
+}
@@ -0,0 +1,21 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    Error, HttpMessage,
+};
+
+use super::extractor::authenticate;
+
+// Require a valid access token for every route of a scope:
+// `web::scope("/api/v1/user").wrap(from_fn(require_auth))`.
+// The validated user is stored in the request so `AuthenticatedUser`
+// handler parameters don't validate the token a second time.
+pub async fn require_auth(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let user = authenticate(req.request()).await?;
+    req.extensions_mut().insert(user);
+    next.call(req).await
+}
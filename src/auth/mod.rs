@@ -1,5 +1,17 @@
+pub mod account_status;
+pub use account_status::AccountStatus;
 pub mod auth;
 pub use auth::Register;
+pub mod cleanup;
+pub mod extractor;
+pub use extractor::AuthenticatedUser;
 pub mod jwt;
+pub mod keys;
+pub mod middleware;
+pub use middleware::require_auth;
+pub mod otp;
+pub mod password_reset;
+pub mod roles;
+pub use roles::{require_moderator, Role};
+pub mod session;
 pub mod utils;
-
@@ -0,0 +1,172 @@
+use rand::Rng;
+use redis::{aio::ConnectionManager, AsyncCommands, RedisError};
+use serde::Deserialize;
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+// Limits applied to OTP delivery and verification, part of `Settings`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OtpConfig {
+    // How long an OTP stays valid in Redis
+    pub ttl_seconds: u64,
+    // Minimum time between two OTP emails to the same address
+    pub resend_cooldown_seconds: u64,
+    // Maximum number of OTP emails per address per day
+    pub daily_limit: u32,
+    // Wrong guesses allowed before the OTP is invalidated
+    pub max_attempts: u32,
+}
+
+pub enum OtpError {
+    // Seconds left before another email may be sent
+    Cooldown(i64),
+    // Seconds left before the daily cap resets
+    DailyLimitReached(i64),
+    Redis(RedisError),
+}
+
+impl From<RedisError> for OtpError {
+    fn from(e: RedisError) -> Self {
+        OtpError::Redis(e)
+    }
+}
+
+impl Default for OtpConfig {
+    fn default() -> Self {
+        OtpConfig {
+            ttl_seconds: 300,
+            resend_cooldown_seconds: 60,
+            daily_limit: 5,
+            max_attempts: 5,
+        }
+    }
+}
+
+// Cooldown and daily cap on one kind of email, counted per address
+pub struct SendLimits {
+    // Prefix of the Redis counters, kinds of email don't share limits
+    pub scope: &'static str,
+    pub cooldown_seconds: u64,
+    pub daily_limit: u32,
+    // Error codes clients get when a limit is hit
+    pub cooldown_code: &'static str,
+    pub daily_limit_code: &'static str,
+}
+
+impl OtpConfig {
+    pub fn send_limits(&self) -> SendLimits {
+        SendLimits {
+            scope: "otp",
+            cooldown_seconds: self.resend_cooldown_seconds,
+            daily_limit: self.daily_limit,
+            cooldown_code: "otp_cooldown",
+            daily_limit_code: "otp_daily_limit",
+        }
+    }
+}
+
+pub fn otp_key(email: &str) -> String {
+    format!("otp:{}", email)
+}
+
+fn cooldown_key(limits: &SendLimits, email: &str) -> String {
+    format!("{}_cooldown:{}", limits.scope, email)
+}
+
+fn daily_key(limits: &SendLimits, email: &str) -> String {
+    format!("{}_daily:{}", limits.scope, email)
+}
+
+fn attempts_key(email: &str) -> String {
+    format!("otp_attempts:{}", email)
+}
+
+pub fn generate_otp() -> String {
+    let mut rng = rand::thread_rng();
+    rng.gen_range(100000..=999999).to_string()
+}
+
+// Reserve the right to send an email, enforcing the cooldown and daily cap
+pub async fn reserve_send(
+    redis_conn: &mut ConnectionManager,
+    limits: &SendLimits,
+    email: &str,
+) -> Result<(), OtpError> {
+    let cooldown_key = cooldown_key(limits, email);
+    let cooldown_set: bool = redis::cmd("SET")
+        .arg(&cooldown_key)
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(limits.cooldown_seconds)
+        .query_async::<Option<String>>(redis_conn)
+        .await?
+        .is_some();
+
+    if !cooldown_set {
+        let remaining: i64 = redis_conn.ttl(&cooldown_key).await?;
+        return Err(OtpError::Cooldown(remaining.max(1)));
+    }
+
+    // The counter is created with a one day expiry the first time it is used
+    let daily_key = daily_key(limits, email);
+    let (sent_today,): (u32,) = redis::pipe()
+        .atomic()
+        .cmd("SET")
+        .arg(&daily_key)
+        .arg(0)
+        .arg("NX")
+        .arg("EX")
+        .arg(SECONDS_PER_DAY)
+        .ignore()
+        .incr(&daily_key, 1)
+        .query_async(redis_conn)
+        .await?;
+
+    if sent_today > limits.daily_limit {
+        let remaining: i64 = redis_conn.ttl(&daily_key).await?;
+        return Err(OtpError::DailyLimitReached(remaining.max(1)));
+    }
+
+    Ok(())
+}
+
+// Store a fresh OTP and reset the failed attempt counter
+pub async fn store_otp(
+    redis_conn: &mut ConnectionManager,
+    config: &OtpConfig,
+    email: &str,
+    otp: &str,
+) -> Result<(), RedisError> {
+    redis::pipe()
+        .atomic()
+        .set_ex(otp_key(email), otp, config.ttl_seconds)
+        .ignore()
+        .del(attempts_key(email))
+        .ignore()
+        .query_async(redis_conn)
+        .await
+}
+
+// Count a wrong guess and return how many have been made against this OTP
+pub async fn record_failed_attempt(
+    redis_conn: &mut ConnectionManager,
+    config: &OtpConfig,
+    email: &str,
+) -> Result<u32, RedisError> {
+    let attempts_key = attempts_key(email);
+    let (attempts,): (u32,) = redis::pipe()
+        .atomic()
+        .incr(&attempts_key, 1)
+        .expire(&attempts_key, config.ttl_seconds as i64)
+        .ignore()
+        .query_async(redis_conn)
+        .await?;
+    Ok(attempts)
+}
+
+// Remove the OTP and its attempt counter
+pub async fn clear_otp(redis_conn: &mut ConnectionManager, email: &str) -> Result<i64, RedisError> {
+    redis_conn.del(&[otp_key(email), attempts_key(email)]).await
+}
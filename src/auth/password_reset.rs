@@ -0,0 +1,59 @@
+use rand::{distributions::Alphanumeric, Rng};
+use redis::{aio::ConnectionManager, AsyncCommands, RedisError};
+use uuid::Uuid;
+
+const RESET_TOKEN_LENGTH: usize = 48;
+
+fn token_key(token: &str) -> String {
+    format!("password_reset:{}", token)
+}
+
+fn user_key(user_id: Uuid) -> String {
+    format!("password_reset_user:{}", user_id)
+}
+
+// Issue a new reset token for the user, replacing any token issued before
+pub async fn issue_reset_token(
+    redis_conn: &mut ConnectionManager,
+    user_id: Uuid,
+    ttl_seconds: u64,
+) -> Result<String, RedisError> {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(RESET_TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+
+    let previous: Option<String> = redis_conn.get(user_key(user_id)).await?;
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    if let Some(previous) = previous {
+        pipe.del(token_key(&previous)).ignore();
+    }
+    pipe.set_ex(token_key(&token), user_id.to_string(), ttl_seconds)
+        .ignore()
+        .set_ex(user_key(user_id), &token, ttl_seconds)
+        .ignore();
+    pipe.query_async::<()>(redis_conn).await?;
+
+    Ok(token)
+}
+
+// Consume a reset token, returning the user it was issued for.
+// GETDEL makes the token single-use even under concurrent requests.
+pub async fn consume_reset_token(
+    redis_conn: &mut ConnectionManager,
+    token: &str,
+) -> Result<Option<Uuid>, RedisError> {
+    let user_id: Option<String> = redis::cmd("GETDEL")
+        .arg(token_key(token))
+        .query_async(redis_conn)
+        .await?;
+
+    let user_id = user_id.and_then(|id| Uuid::parse_str(&id).ok());
+    if let Some(user_id) = user_id {
+        let _: i64 = redis_conn.del(user_key(user_id)).await?;
+    }
+    Ok(user_id)
+}
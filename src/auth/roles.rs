@@ -0,0 +1,95 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    Error, HttpMessage,
+};
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+use super::extractor::{authenticate, AuthenticatedUser};
+use crate::common::AppError;
+
+// Ordered by rights, each role can do everything the previous one can
+#[derive(
+    sqlx::Type,
+    Debug,
+    Default,
+    Deserialize,
+    Display,
+    Serialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum Role {
+    #[default]
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    // Whether someone with this role may moderate an account with `other`.
+    // Admins can act on anyone, moderators only on regular users.
+    pub fn outranks(self, other: Role) -> bool {
+        self == Role::Admin || self > other
+    }
+}
+
+impl AuthenticatedUser {
+    // Whether the user has at least `role`
+    pub fn has_role(&self, role: Role) -> bool {
+        self.claims.role >= role
+    }
+}
+
+// Require a moderator or admin for every route of a scope. Wrap it inside
+// `require_auth`, or it validates the token itself.
+pub async fn require_moderator(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let user = req.extensions().get::<AuthenticatedUser>().cloned();
+    let user = match user {
+        Some(user) => user,
+        None => authenticate(req.request()).await?,
+    };
+
+    if !user.has_role(Role::Moderator) {
+        return Err(AppError::Forbidden("Insufficient Permissions".to_string()).into());
+    }
+
+    req.extensions_mut().insert(user);
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admins_outrank_everyone() {
+        assert!(Role::Admin.outranks(Role::User));
+        assert!(Role::Admin.outranks(Role::Moderator));
+        assert!(Role::Admin.outranks(Role::Admin));
+    }
+
+    #[test]
+    fn moderators_only_outrank_users() {
+        assert!(Role::Moderator.outranks(Role::User));
+        assert!(!Role::Moderator.outranks(Role::Moderator));
+        assert!(!Role::Moderator.outranks(Role::Admin));
+    }
+
+    #[test]
+    fn users_outrank_no_one() {
+        assert!(!Role::User.outranks(Role::User));
+        assert!(!Role::User.outranks(Role::Moderator));
+        assert!(!Role::User.outranks(Role::Admin));
+    }
+}
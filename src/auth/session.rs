@@ -0,0 +1,244 @@
+use rand::{distributions::Alphanumeric, Rng};
+use redis::{aio::ConnectionManager, AsyncCommands, RedisError, Script};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+const REFRESH_TOKEN_LENGTH: usize = 64;
+
+// Lifetimes of the tokens handed out at sign-in, part of `Settings`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SessionConfig {
+    pub access_token_ttl_minutes: i64,
+    pub refresh_token_ttl_days: i64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            access_token_ttl_minutes: 15,
+            refresh_token_ttl_days: 30,
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn refresh_token_ttl_seconds(&self) -> i64 {
+        self.refresh_token_ttl_days * 24 * 60 * 60
+    }
+}
+
+pub enum RefreshOutcome {
+    Rotated {
+        user_id: Uuid,
+        session_id: Uuid,
+        refresh_token: String,
+    },
+    // Unknown, expired or already revoked refresh token
+    Invalid,
+    // A refresh token that was already rotated was presented again, so the
+    // session has been revoked
+    ReuseDetected,
+}
+
+fn session_key(session_id: Uuid) -> String {
+    format!("session:{}", session_id)
+}
+
+fn refresh_token_key(hash: &str) -> String {
+    format!("refresh_token:{}", hash)
+}
+
+fn user_sessions_key(user_id: Uuid) -> String {
+    format!("user_sessions:{}", user_id)
+}
+
+// Only a hash of the refresh token is kept in Redis
+fn hash_refresh_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn generate_refresh_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(REFRESH_TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+// Start a new session for the user and return its id and first refresh token
+pub async fn create_session(
+    redis_conn: &mut ConnectionManager,
+    config: &SessionConfig,
+    user_id: Uuid,
+) -> Result<(Uuid, String), RedisError> {
+    let session_id = Uuid::new_v4();
+    let refresh_token = generate_refresh_token();
+    let refresh_hash = hash_refresh_token(&refresh_token);
+    let ttl = config.refresh_token_ttl_seconds();
+
+    redis::pipe()
+        .atomic()
+        .hset_multiple(
+            session_key(session_id),
+            &[
+                ("user_id", user_id.to_string()),
+                ("refresh_hash", refresh_hash.clone()),
+                ("created_at", chrono::Utc::now().to_rfc3339()),
+            ],
+        )
+        .ignore()
+        .expire(session_key(session_id), ttl)
+        .ignore()
+        .set_ex(
+            refresh_token_key(&refresh_hash),
+            session_id.to_string(),
+            ttl as u64,
+        )
+        .ignore()
+        .sadd(user_sessions_key(user_id), session_id.to_string())
+        .ignore()
+        .expire(user_sessions_key(user_id), ttl)
+        .ignore()
+        .query_async::<()>(redis_conn)
+        .await?;
+
+    Ok((session_id, refresh_token))
+}
+
+// Swap the session's refresh token for a new one. Old refresh tokens stay
+// mapped to their session so that presenting one again can be detected.
+const ROTATE_SCRIPT: &str = r#"
+local session_id = redis.call('GET', KEYS[1])
+if not session_id then
+    return {'invalid'}
+end
+local session_key = 'session:' .. session_id
+local current = redis.call('HGET', session_key, 'refresh_hash')
+if not current then
+    return {'invalid'}
+end
+if current ~= ARGV[1] then
+    return {'reused', session_id}
+end
+redis.call('HSET', session_key, 'refresh_hash', ARGV[2])
+redis.call('EXPIRE', session_key, ARGV[3])
+redis.call('SET', 'refresh_token:' .. ARGV[2], session_id, 'EX', ARGV[3])
+return {'rotated', session_id, redis.call('HGET', session_key, 'user_id')}
+"#;
+
+pub async fn rotate_refresh_token(
+    redis_conn: &mut ConnectionManager,
+    config: &SessionConfig,
+    refresh_token: &str,
+) -> Result<RefreshOutcome, RedisError> {
+    let old_hash = hash_refresh_token(refresh_token);
+    let new_token = generate_refresh_token();
+    let new_hash = hash_refresh_token(&new_token);
+
+    let result: Vec<String> = Script::new(ROTATE_SCRIPT)
+        .key(refresh_token_key(&old_hash))
+        .arg(&old_hash)
+        .arg(&new_hash)
+        .arg(config.refresh_token_ttl_seconds())
+        .invoke_async(redis_conn)
+        .await?;
+
+    let ids: Vec<Uuid> = result
+        .iter()
+        .skip(1)
+        .filter_map(|id| Uuid::parse_str(id).ok())
+        .collect();
+
+    match (result.first().map(String::as_str), ids.as_slice()) {
+        (Some("rotated"), [session_id, user_id]) => Ok(RefreshOutcome::Rotated {
+            user_id: *user_id,
+            session_id: *session_id,
+            refresh_token: new_token,
+        }),
+        (Some("reused"), [session_id]) => {
+            revoke_session(redis_conn, *session_id).await?;
+            Ok(RefreshOutcome::ReuseDetected)
+        }
+        _ => Ok(RefreshOutcome::Invalid),
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionInfo {
+    pub session_id: Uuid,
+    pub created_at: Option<String>,
+}
+
+// Live sessions of the user
+pub async fn list_sessions(
+    redis_conn: &mut ConnectionManager,
+    user_id: Uuid,
+) -> Result<Vec<SessionInfo>, RedisError> {
+    let session_ids: Vec<String> = redis_conn.smembers(user_sessions_key(user_id)).await?;
+    let session_ids: Vec<Uuid> = session_ids
+        .iter()
+        .filter_map(|id| Uuid::parse_str(id).ok())
+        .collect();
+    if session_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut pipe = redis::pipe();
+    for session_id in &session_ids {
+        pipe.hget(session_key(*session_id), &["user_id", "created_at"]);
+    }
+    let fields: Vec<(Option<String>, Option<String>)> = pipe.query_async(redis_conn).await?;
+
+    // Expired sessions linger in the set until the user's next sign-in
+    Ok(session_ids
+        .into_iter()
+        .zip(fields)
+        .filter(|(_, (owner, _))| owner.as_deref() == Some(user_id.to_string().as_str()))
+        .map(|(session_id, (_, created_at))| SessionInfo {
+            session_id,
+            created_at,
+        })
+        .collect())
+}
+
+// Whether the session exists and belongs to the user
+pub async fn is_session_active(
+    redis_conn: &mut ConnectionManager,
+    session_id: Uuid,
+    user_id: Uuid,
+) -> Result<bool, RedisError> {
+    let owner: Option<String> = redis_conn.hget(session_key(session_id), "user_id").await?;
+    Ok(owner.as_deref() == Some(user_id.to_string().as_str()))
+}
+
+// End a single session, invalidating its access and refresh tokens
+pub async fn revoke_session(
+    redis_conn: &mut ConnectionManager,
+    session_id: Uuid,
+) -> Result<(), RedisError> {
+    let owner: Option<String> = redis_conn.hget(session_key(session_id), "user_id").await?;
+    let mut pipe = redis::pipe();
+    pipe.atomic().del(session_key(session_id)).ignore();
+    if let Some(user_id) = owner.and_then(|id| Uuid::parse_str(&id).ok()) {
+        pipe.srem(user_sessions_key(user_id), session_id.to_string())
+            .ignore();
+    }
+    pipe.query_async(redis_conn).await
+}
+
+// End every session of the user
+pub async fn revoke_all_sessions(
+    redis_conn: &mut ConnectionManager,
+    user_id: Uuid,
+) -> Result<(), RedisError> {
+    let session_ids: Vec<String> = redis_conn.smembers(user_sessions_key(user_id)).await?;
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for session_id in session_ids {
+        pipe.del(format!("session:{}", session_id)).ignore();
+    }
+    pipe.del(user_sessions_key(user_id)).ignore();
+    pipe.query_async(redis_conn).await
+}
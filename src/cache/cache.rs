@@ -0,0 +1,366 @@
+use std::{future::Future, time::Duration};
+
+use rand::Rng;
+use redis::{aio::ConnectionManager, AsyncCommands, RedisError, Script};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::config::CacheSettings;
+
+// How often and how long a caller waits for another one loading the same key
+const LOCK_WAIT_INTERVAL: Duration = Duration::from_millis(50);
+const LOCK_WAIT_ATTEMPTS: u32 = 20;
+
+// Only the holder of the lock may release it, it may have expired and been
+// taken by someone else in the meantime
+const RELEASE_LOCK_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+// How entries are stored. The wrapper tells a cached miss, `{"v":null}`, apart
+// from a key that isn't cached at all.
+#[derive(Serialize, Deserialize)]
+struct Cached<V> {
+    v: V,
+}
+
+// Every entry kept in the cache
+#[derive(Debug, Clone, Copy)]
+pub enum CacheKey {
+    // The user's profile, see `User::get_user_basic_data`
+    UserData(Uuid),
+    Preferences(Uuid),
+    // The user's ranked discover feed
+    DiscoverFeed(Uuid),
+}
+
+// Groups of entries that can be dropped together
+#[derive(Debug, Clone, Copy)]
+pub enum CacheTag {
+    // Every entry about the user
+    User(Uuid),
+}
+
+impl CacheKey {
+    fn redis_key(&self) -> String {
+        match self {
+            CacheKey::UserData(user_id) => format!("user_data:{}", user_id),
+            CacheKey::Preferences(user_id) => format!("user_preferences:{}", user_id),
+            CacheKey::DiscoverFeed(user_id) => format!("discover_feed:{}", user_id),
+        }
+    }
+
+    fn ttl_seconds(&self, settings: &CacheSettings) -> u64 {
+        match self {
+            CacheKey::UserData(_) => settings.user_data_ttl_seconds,
+            CacheKey::Preferences(_) => settings.preferences_ttl_seconds,
+            CacheKey::DiscoverFeed(_) => settings.discover_feed_ttl_seconds,
+        }
+    }
+
+    fn tags(&self) -> Vec<CacheTag> {
+        match self {
+            CacheKey::UserData(user_id)
+            | CacheKey::Preferences(user_id)
+            | CacheKey::DiscoverFeed(user_id) => vec![CacheTag::User(*user_id)],
+        }
+    }
+}
+
+impl CacheTag {
+    fn redis_key(&self) -> String {
+        match self {
+            CacheTag::User(user_id) => format!("cache_tag:user:{}", user_id),
+        }
+    }
+}
+
+// Read-through cache in Redis. Misses are loaded by one caller at a time,
+// missing values are cached for a short while too, and TTLs get some jitter
+// so entries written together don't expire together. When Redis is
+// unreachable values are loaded straight from the source.
+#[derive(Clone)]
+pub struct Cache {
+    redis: ConnectionManager,
+    settings: CacheSettings,
+}
+
+impl Cache {
+    pub fn new(redis: ConnectionManager, settings: CacheSettings) -> Self {
+        Cache { redis, settings }
+    }
+
+    // Return the cached value, calling `load` on a miss and caching what it
+    // returns. `None` means the value doesn't exist.
+    pub async fn get_or_load<T, E, F, Fut>(&self, key: CacheKey, load: F) -> Result<Option<T>, E>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<T>, E>>,
+    {
+        let redis_key = key.redis_key();
+        let mut redis_conn = self.redis.clone();
+
+        match lookup(&mut redis_conn, &redis_key).await {
+            Ok(Some(cached)) => return Ok(cached),
+            Ok(None) => {}
+            Err(e) => {
+                println!("Cache unavailable, loading {} directly: {}", redis_key, e);
+                return load().await;
+            }
+        }
+
+        // Stampede protection, whoever takes the lock loads the value while
+        // the others wait for it to show up
+        let lock_key = format!("cache_lock:{}", redis_key);
+        let lock_token = Uuid::new_v4().to_string();
+        let locked: Result<Option<String>, RedisError> = redis::cmd("SET")
+            .arg(&lock_key)
+            .arg(&lock_token)
+            .arg("NX")
+            .arg("PX")
+            .arg(self.settings.lock_timeout_ms)
+            .query_async(&mut redis_conn)
+            .await;
+
+        let holds_lock = match locked {
+            Ok(locked) => locked.is_some(),
+            Err(e) => {
+                println!("Cache unavailable, loading {} directly: {}", redis_key, e);
+                return load().await;
+            }
+        };
+
+        if !holds_lock {
+            for _ in 0..LOCK_WAIT_ATTEMPTS {
+                tokio::time::sleep(LOCK_WAIT_INTERVAL).await;
+                if let Ok(Some(cached)) = lookup(&mut redis_conn, &redis_key).await {
+                    return Ok(cached);
+                }
+            }
+            // Whoever holds the lock is too slow, load it ourselves
+        }
+
+        let loaded = load().await;
+        if let Ok(value) = &loaded {
+            self.store(&mut redis_conn, key, value).await;
+        }
+        if holds_lock {
+            let _: Result<i64, RedisError> = Script::new(RELEASE_LOCK_SCRIPT)
+                .key(&lock_key)
+                .arg(&lock_token)
+                .invoke_async(&mut redis_conn)
+                .await;
+        }
+        loaded
+    }
+
+    async fn store<T: Serialize>(
+        &self,
+        redis_conn: &mut ConnectionManager,
+        key: CacheKey,
+        value: &Option<T>,
+    ) {
+        let Ok(serialized) = encode(value) else {
+            return;
+        };
+        let ttl = match value {
+            Some(_) => key.ttl_seconds(&self.settings),
+            None => self.settings.negative_ttl_seconds,
+        };
+        let ttl = with_jitter(ttl, self.settings.ttl_jitter_percent);
+
+        let redis_key = key.redis_key();
+        let mut pipe = redis::pipe();
+        pipe.set_ex(&redis_key, serialized, ttl).ignore();
+        // Tags outlive every entry they point to
+        for tag in key.tags() {
+            pipe.sadd(tag.redis_key(), &redis_key)
+                .ignore()
+                .expire(tag.redis_key(), longest_ttl(&self.settings) as i64)
+                .ignore();
+        }
+        let stored: Result<(), RedisError> = pipe.query_async(redis_conn).await;
+        if let Err(e) = stored {
+            println!("Failed to cache {}: {}", redis_key, e);
+        }
+    }
+
+    // Drop the given entries
+    pub async fn invalidate(&self, keys: &[CacheKey]) {
+        let redis_keys: Vec<String> = keys.iter().map(CacheKey::redis_key).collect();
+        let deleted: Result<i64, RedisError> = self.redis.clone().del(&redis_keys).await;
+        if let Err(e) = deleted {
+            println!("Failed to invalidate cache entries: {}", e);
+        }
+    }
+
+    // Drop every entry carrying the tag
+    pub async fn invalidate_tag(&self, tag: CacheTag) {
+        let mut redis_conn = self.redis.clone();
+        let tag_key = tag.redis_key();
+
+        let members: Result<Vec<String>, RedisError> = redis_conn.smembers(&tag_key).await;
+        let mut redis_keys = match members {
+            Ok(members) => members,
+            Err(e) => return println!("Failed to invalidate cache tag {}: {}", tag_key, e),
+        };
+        redis_keys.push(tag_key);
+
+        let deleted: Result<i64, RedisError> = redis_conn.del(&redis_keys).await;
+        if let Err(e) = deleted {
+            println!("Failed to invalidate cache tag: {}", e);
+        }
+    }
+}
+
+fn with_jitter(ttl: u64, jitter_percent: u64) -> u64 {
+    let max_jitter = ttl * jitter_percent / 100;
+    ttl + rand::thread_rng().gen_range(0..=max_jitter)
+}
+
+// Longest any entry may live, jitter included
+fn longest_ttl(settings: &CacheSettings) -> u64 {
+    let longest = [
+        settings.user_data_ttl_seconds,
+        settings.preferences_ttl_seconds,
+        settings.discover_feed_ttl_seconds,
+        settings.negative_ttl_seconds,
+    ]
+    .into_iter()
+    .max()
+    .unwrap_or_default();
+    longest + longest * settings.ttl_jitter_percent / 100
+}
+
+// `Some(None)` is a cached miss. Entries that fail to deserialize, e.g. after
+// the type changed, count as not cached.
+async fn lookup<T: DeserializeOwned>(
+    redis_conn: &mut ConnectionManager,
+    redis_key: &str,
+) -> Result<Option<Option<T>>, RedisError> {
+    let cached: Option<String> = redis_conn.get(redis_key).await?;
+    Ok(cached.and_then(|cached| decode(&cached)))
+}
+
+fn encode<T: Serialize>(value: &Option<T>) -> serde_json::Result<String> {
+    serde_json::to_string(&Cached { v: value })
+}
+
+fn decode<T: DeserializeOwned>(cached: &str) -> Option<Option<T>> {
+    serde_json::from_str::<Cached<Option<T>>>(cached)
+        .ok()
+        .map(|cached| cached.v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> CacheSettings {
+        CacheSettings {
+            user_data_ttl_seconds: 3600,
+            preferences_ttl_seconds: 1800,
+            discover_feed_ttl_seconds: 600,
+            negative_ttl_seconds: 60,
+            ttl_jitter_percent: 10,
+            lock_timeout_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn keys_are_namespaced_per_entry() {
+        let user_id = Uuid::new_v4();
+
+        assert_eq!(
+            CacheKey::UserData(user_id).redis_key(),
+            format!("user_data:{}", user_id)
+        );
+        assert_eq!(
+            CacheKey::Preferences(user_id).redis_key(),
+            format!("user_preferences:{}", user_id)
+        );
+        assert_eq!(
+            CacheKey::DiscoverFeed(user_id).redis_key(),
+            format!("discover_feed:{}", user_id)
+        );
+        assert_eq!(
+            CacheTag::User(user_id).redis_key(),
+            format!("cache_tag:user:{}", user_id)
+        );
+    }
+
+    #[test]
+    fn keys_use_their_own_ttl() {
+        let settings = settings();
+        let user_id = Uuid::new_v4();
+
+        assert_eq!(CacheKey::UserData(user_id).ttl_seconds(&settings), 3600);
+        assert_eq!(CacheKey::Preferences(user_id).ttl_seconds(&settings), 1800);
+        assert_eq!(CacheKey::DiscoverFeed(user_id).ttl_seconds(&settings), 600);
+    }
+
+    #[test]
+    fn keys_are_tagged_with_their_user() {
+        let user_id = Uuid::new_v4();
+        let tag_key = CacheTag::User(user_id).redis_key();
+
+        for key in [
+            CacheKey::UserData(user_id),
+            CacheKey::Preferences(user_id),
+            CacheKey::DiscoverFeed(user_id),
+        ] {
+            let tags: Vec<String> = key.tags().iter().map(CacheTag::redis_key).collect();
+            assert_eq!(tags, vec![tag_key.clone()]);
+        }
+    }
+
+    #[test]
+    fn cached_miss_reads_back_as_miss() {
+        let stored = encode::<String>(&None).unwrap();
+        assert_eq!(stored, r#"{"v":null}"#);
+        assert_eq!(decode::<String>(&stored), Some(None));
+    }
+
+    #[test]
+    fn cached_value_reads_back() {
+        let stored = encode(&Some("Alice".to_string())).unwrap();
+        assert_eq!(decode::<String>(&stored), Some(Some("Alice".to_string())));
+    }
+
+    #[test]
+    fn undecodable_entries_are_not_cached() {
+        // Written before the wrapper, or for another type
+        assert_eq!(decode::<String>("null"), None);
+        assert_eq!(decode::<String>(r#"{"v":42}"#), None);
+    }
+
+    #[test]
+    fn jitter_stays_within_percent() {
+        for _ in 0..1000 {
+            let ttl = with_jitter(600, 10);
+            assert!((600..=660).contains(&ttl), "{} out of range", ttl);
+        }
+    }
+
+    #[test]
+    fn no_jitter_keeps_ttl() {
+        assert_eq!(with_jitter(600, 0), 600);
+        assert_eq!(with_jitter(0, 10), 0);
+    }
+
+    #[test]
+    fn tags_outlive_every_entry() {
+        let settings = settings();
+        assert_eq!(longest_ttl(&settings), 3960);
+
+        let negative_longest = CacheSettings {
+            negative_ttl_seconds: 7200,
+            ..settings
+        };
+        assert_eq!(longest_ttl(&negative_longest), 7920);
+    }
+}
@@ -0,0 +1,2 @@
+pub mod cache;
+pub use cache::{Cache, CacheKey, CacheTag};
@@ -0,0 +1,525 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool};
+use uuid::Uuid;
+
+use super::{
+    hub::{ChatHub, ServerEvent},
+    unread,
+};
+use crate::{
+    auth::AuthenticatedUser,
+    common::{AppError, ResponseToSend},
+};
+
+pub const MAX_MESSAGE_LENGTH: usize = 2000;
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+// Delivered when the recipient connects, anything older shows up in history
+const MAX_PENDING_MESSAGES: i64 = 200;
+
+// A conversation as seen by one of its two users
+#[derive(Serialize, FromRow, Debug)]
+pub struct Conversation {
+    id: Uuid,
+    match_id: Uuid,
+    user_id: Uuid,
+    created_at: Option<DateTime<Utc>>,
+    last_message_at: Option<DateTime<Utc>>,
+}
+
+// Entry in the conversation list with its latest message
+#[derive(Serialize, FromRow, Debug)]
+pub struct ConversationSummary {
+    id: Uuid,
+    match_id: Uuid,
+    user_id: Uuid,
+    firstname: Option<String>,
+    profile_picture_url: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    last_message_at: Option<DateTime<Utc>>,
+    last_message: Option<sqlx::types::Json<Message>>,
+    #[sqlx(skip)]
+    unread_count: i64,
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum MessageStatus {
+    Sent,
+    Delivered,
+    Read,
+}
+
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct Message {
+    pub id: Uuid,
+    pub conversation_id: Uuid,
+    pub sender_id: Uuid,
+    pub body: String,
+    pub status: MessageStatus,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OpenConversation {
+    user_id: Uuid,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SendMessage {
+    body: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HistoryQuery {
+    // Id of the oldest message already loaded, older ones are returned
+    before: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MessagePage {
+    messages: Vec<Message>,
+    has_more: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UnreadCount {
+    total: i64,
+    conversations: i64,
+}
+
+#[derive(FromRow)]
+struct DeliveredRow {
+    id: Uuid,
+    conversation_id: Uuid,
+    sender_id: Uuid,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChatError {
+    // Conversation doesn't exist or the user isn't part of it
+    #[error("Conversation Not Found")]
+    NotFound,
+    #[error("{0}")]
+    InvalidMessage(String),
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<ChatError> for AppError {
+    fn from(e: ChatError) -> Self {
+        match e {
+            ChatError::NotFound => AppError::NotFound(e.to_string()),
+            ChatError::InvalidMessage(message) => AppError::BadRequest(message),
+            ChatError::Database(e) => AppError::Database(e),
+        }
+    }
+}
+
+pub struct Chat;
+
+impl Chat {
+    // Get Conversations, most recently active first
+    pub async fn get_conversations(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        auth_user: AuthenticatedUser,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+
+        let mut conversations = sqlx::query_as::<_, ConversationSummary>(
+            "SELECT c.id, c.match_id, other.id AS user_id, ud.firstname, ud.profile_picture_url, c.created_at, c.last_message_at,
+                    (SELECT to_jsonb(m) FROM (
+                        SELECT id, conversation_id, sender_id, body, status, created_at, delivered_at, read_at
+                        FROM messages
+                        WHERE conversation_id = c.id
+                        ORDER BY created_at DESC, id DESC
+                        LIMIT 1
+                    ) m) AS last_message
+             FROM conversations c
+             JOIN users other ON other.id = CASE WHEN c.user_one_id = $1 THEN c.user_two_id ELSE c.user_one_id END
+             LEFT JOIN usersdata ud ON ud.user_id = other.id
+             WHERE c.user_one_id = $1 OR c.user_two_id = $1
+             ORDER BY COALESCE(c.last_message_at, c.created_at) DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&**db)
+        .await?;
+
+        let counts = unread::counts(&db, &redis, user_id).await?;
+        for conversation in &mut conversations {
+            conversation.unread_count = counts.get(&conversation.id).copied().unwrap_or_default();
+        }
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Conversations Fetch Successfully".to_string(),
+            data: Some(conversations),
+        }))
+    }
+
+    // Get Unread Count, for the badge
+    pub async fn get_unread_count(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        auth_user: AuthenticatedUser,
+    ) -> Result<HttpResponse, AppError> {
+        let counts = unread::counts(&db, &redis, auth_user.user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Unread Count Fetch Successfully".to_string(),
+            data: Some(UnreadCount {
+                total: counts.values().sum(),
+                conversations: counts.len() as i64,
+            }),
+        }))
+    }
+
+    // Open Conversation with a match, or return the existing one
+    pub async fn open_conversation(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        body: Json<OpenConversation>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+        let (user_one_id, user_two_id) = if user_id < body.user_id {
+            (user_id, body.user_id)
+        } else {
+            (body.user_id, user_id)
+        };
+
+        let match_id: Option<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM matches WHERE user_one_id = $1 AND user_two_id = $2",
+        )
+        .bind(user_one_id)
+        .bind(user_two_id)
+        .fetch_optional(&**db)
+        .await?;
+
+        let Some(match_id) = match_id else {
+            return Err(AppError::Forbidden(
+                "You can only message your matches".to_string(),
+            ));
+        };
+
+        sqlx::query(
+            "INSERT INTO conversations (id, match_id, user_one_id, user_two_id) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (match_id) DO NOTHING",
+        )
+        .bind(Uuid::new_v4())
+        .bind(match_id)
+        .bind(user_one_id)
+        .bind(user_two_id)
+        .execute(&**db)
+        .await?;
+
+        let conversation = sqlx::query_as::<_, Conversation>(
+            "SELECT id, match_id, $2::UUID AS user_id, created_at, last_message_at FROM conversations WHERE match_id = $1",
+        )
+        .bind(match_id)
+        .bind(body.user_id)
+        .fetch_one(&**db)
+        .await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Conversation Opened Successfully".to_string(),
+            data: Some(conversation),
+        }))
+    }
+
+    // Get Messages, newest first
+    pub async fn get_messages(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+        query: Query<HistoryQuery>,
+    ) -> Result<HttpResponse, AppError> {
+        let conversation_id = path.into_inner();
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+
+        other_participant(&db, conversation_id, auth_user.user_id).await?;
+
+        // Fetch one extra row to know whether there is another page
+        let mut messages = sqlx::query_as::<_, Message>(
+            "SELECT id, conversation_id, sender_id, body, status, created_at, delivered_at, read_at
+             FROM messages
+             WHERE conversation_id = $1
+               AND ($2::UUID IS NULL OR (created_at, id) < (SELECT created_at, id FROM messages WHERE id = $2 AND conversation_id = $1))
+             ORDER BY created_at DESC, id DESC
+             LIMIT $3",
+        )
+        .bind(conversation_id)
+        .bind(query.before)
+        .bind(limit + 1)
+        .fetch_all(&**db)
+        .await?;
+
+        let has_more = messages.len() as i64 > limit;
+        messages.truncate(limit as usize);
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Messages Fetch Successfully".to_string(),
+            data: Some(MessagePage { messages, has_more }),
+        }))
+    }
+
+    // Send Message over REST, for clients without an open WebSocket
+    pub async fn post_message(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        hub: Data<ChatHub>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+        body: Json<SendMessage>,
+    ) -> Result<HttpResponse, AppError> {
+        let message = send_message(
+            &db,
+            &redis,
+            &hub,
+            path.into_inner(),
+            auth_user.user_id,
+            &body.body,
+        )
+        .await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Message Sent Successfully".to_string(),
+            data: Some(message),
+        }))
+    }
+
+    // Mark Conversation as Read
+    pub async fn mark_conversation_read(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        hub: Data<ChatHub>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        mark_read(&db, &redis, &hub, path.into_inner(), auth_user.user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "Conversation Marked As Read".to_string(),
+            data: None,
+        }))
+    }
+}
+
+// The other user of the conversation, if `user_id` is part of it
+pub async fn other_participant(
+    db: &PgPool,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Result<Uuid, ChatError> {
+    let other: Option<Uuid> = sqlx::query_scalar(
+        "SELECT CASE WHEN user_one_id = $2 THEN user_two_id ELSE user_one_id END
+         FROM conversations WHERE id = $1 AND (user_one_id = $2 OR user_two_id = $2)",
+    )
+    .bind(conversation_id)
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+
+    other.ok_or(ChatError::NotFound)
+}
+
+// Store a message and push it to both users
+pub async fn send_message(
+    db: &PgPool,
+    redis: &ConnectionManager,
+    hub: &ChatHub,
+    conversation_id: Uuid,
+    sender_id: Uuid,
+    body: &str,
+) -> Result<Message, ChatError> {
+    let body = body.trim();
+    if body.is_empty() {
+        return Err(ChatError::InvalidMessage(
+            "Message must not be empty".to_string(),
+        ));
+    }
+    if body.chars().count() > MAX_MESSAGE_LENGTH {
+        return Err(ChatError::InvalidMessage(format!(
+            "Message must be at most {} characters",
+            MAX_MESSAGE_LENGTH
+        )));
+    }
+
+    let recipient_id = other_participant(db, conversation_id, sender_id).await?;
+
+    let mut tx = db.begin().await?;
+    let message = sqlx::query_as::<_, Message>(
+        "INSERT INTO messages (id, conversation_id, sender_id, body) VALUES ($1, $2, $3, $4)
+         RETURNING id, conversation_id, sender_id, body, status, created_at, delivered_at, read_at",
+    )
+    .bind(Uuid::new_v4())
+    .bind(conversation_id)
+    .bind(sender_id)
+    .bind(body)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query("UPDATE conversations SET last_message_at = $1 WHERE id = $2")
+        .bind(message.created_at)
+        .bind(conversation_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    unread::increment(redis, recipient_id, conversation_id).await;
+
+    // The sender gets it too so their other devices stay in sync
+    let event = ServerEvent::Message {
+        message: message.clone(),
+    };
+    hub.publish(recipient_id, &event).await;
+    hub.publish(sender_id, &event).await;
+
+    Ok(message)
+}
+
+// Tell the other user that `user_id` is typing
+pub async fn send_typing(
+    db: &PgPool,
+    hub: &ChatHub,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Result<(), ChatError> {
+    let recipient_id = other_participant(db, conversation_id, user_id).await?;
+    hub.publish(
+        recipient_id,
+        &ServerEvent::Typing {
+            conversation_id,
+            user_id,
+        },
+    )
+    .await;
+    Ok(())
+}
+
+// Mark everything the other user sent as read and send them a read receipt
+pub async fn mark_read(
+    db: &PgPool,
+    redis: &ConnectionManager,
+    hub: &ChatHub,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Result<(), ChatError> {
+    let sender_id = other_participant(db, conversation_id, user_id).await?;
+    let read_at = Utc::now();
+
+    let updated = sqlx::query(
+        "UPDATE messages SET status = $1, read_at = $2, delivered_at = COALESCE(delivered_at, $2)
+         WHERE conversation_id = $3 AND sender_id = $4 AND status <> $1",
+    )
+    .bind(MessageStatus::Read)
+    .bind(read_at)
+    .bind(conversation_id)
+    .bind(sender_id)
+    .execute(db)
+    .await?;
+
+    unread::clear(redis, user_id, conversation_id).await;
+
+    if updated.rows_affected() > 0 {
+        hub.publish(
+            sender_id,
+            &ServerEvent::Read {
+                conversation_id,
+                user_id,
+                read_at,
+            },
+        )
+        .await;
+    }
+    Ok(())
+}
+
+// Messages sent to the user that haven't reached any of their devices yet,
+// oldest first
+pub async fn pending_messages(db: &PgPool, user_id: Uuid) -> Result<Vec<Message>, sqlx::Error> {
+    sqlx::query_as::<_, Message>(
+        "SELECT m.id, m.conversation_id, m.sender_id, m.body, m.status, m.created_at, m.delivered_at, m.read_at
+         FROM messages m
+         JOIN conversations c ON c.id = m.conversation_id
+         WHERE (c.user_one_id = $1 OR c.user_two_id = $1)
+           AND m.sender_id <> $1
+           AND m.status = $2
+         ORDER BY m.created_at, m.id
+         LIMIT $3",
+    )
+    .bind(user_id)
+    .bind(MessageStatus::Sent)
+    .bind(MAX_PENDING_MESSAGES)
+    .fetch_all(db)
+    .await
+}
+
+// Mark messages as delivered to `user_id` and send the senders a receipt.
+// Messages already delivered or read are left alone.
+pub async fn mark_delivered(
+    db: &PgPool,
+    hub: &ChatHub,
+    user_id: Uuid,
+    message_ids: &[Uuid],
+) -> Result<(), ChatError> {
+    let delivered_at = Utc::now();
+
+    let delivered = sqlx::query_as::<_, DeliveredRow>(
+        "UPDATE messages m SET status = $1, delivered_at = $2
+         FROM conversations c
+         WHERE m.id = ANY($3)
+           AND c.id = m.conversation_id
+           AND (c.user_one_id = $4 OR c.user_two_id = $4)
+           AND m.sender_id <> $4
+           AND m.status = $5
+         RETURNING m.id, m.conversation_id, m.sender_id",
+    )
+    .bind(MessageStatus::Delivered)
+    .bind(delivered_at)
+    .bind(message_ids)
+    .bind(user_id)
+    .bind(MessageStatus::Sent)
+    .fetch_all(db)
+    .await?;
+
+    let mut receipts: HashMap<(Uuid, Uuid), Vec<Uuid>> = HashMap::new();
+    for row in delivered {
+        receipts
+            .entry((row.conversation_id, row.sender_id))
+            .or_default()
+            .push(row.id);
+    }
+
+    for ((conversation_id, sender_id), message_ids) in receipts {
+        hub.publish(
+            sender_id,
+            &ServerEvent::Delivered {
+                conversation_id,
+                message_ids,
+                delivered_at,
+            },
+        )
+        .await;
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use redis::{
+    aio::{ConnectionManager, PubSub, PubSubSink, PubSubStream},
+    AsyncCommands,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex};
+use uuid::Uuid;
+
+use super::chat::Message;
+use crate::connections::connect_to_redis_pubsub;
+
+// Wait between attempts to re-open the pub/sub connection after it dropped
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+// Events pushed to connected clients
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    Message {
+        message: Message,
+    },
+    Typing {
+        conversation_id: Uuid,
+        user_id: Uuid,
+    },
+    Read {
+        conversation_id: Uuid,
+        user_id: Uuid,
+        read_at: DateTime<Utc>,
+    },
+    // Messages reached one of the recipient's devices
+    Delivered {
+        conversation_id: Uuid,
+        message_ids: Vec<Uuid>,
+        delivered_at: DateTime<Utc>,
+    },
+    // Kept in the inbox until received, see `inbox::store`
+    Match {
+        event_id: Uuid,
+        match_id: Uuid,
+        user_id: Uuid,
+        matched_at: DateTime<Utc>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl ServerEvent {
+    // Id of the inbox entry holding this event, if it is kept there
+    pub fn inbox_id(&self) -> Option<Uuid> {
+        match self {
+            ServerEvent::Match { event_id, .. } => Some(*event_id),
+            _ => None,
+        }
+    }
+}
+
+// Open a new pub/sub connection and subscribe it to the channels of every
+// user connected to this instance
+async fn resubscribe(
+    redis_url: &str,
+    registry: &Mutex<Registry>,
+) -> Result<PubSubStream, redis::RedisError> {
+    let (mut sink, stream) = connect_to_redis_pubsub(redis_url).await?.split();
+
+    let mut registry = registry.lock().await;
+    for user_id in registry.sessions.keys() {
+        sink.subscribe(user_channel(*user_id)).await?;
+    }
+    registry.sink = sink;
+
+    Ok(stream)
+}
+
+fn user_channel(user_id: Uuid) -> String {
+    format!("chat:user:{}", user_id)
+}
+
+struct Registry {
+    // Local WebSocket sessions of each user, keyed by connection id
+    sessions: HashMap<Uuid, HashMap<Uuid, mpsc::UnboundedSender<String>>>,
+    sink: PubSubSink,
+}
+
+// Routes chat events to WebSocket sessions across server instances.
+//
+// Events are published to a Redis channel per user. Each instance subscribes
+// to the channels of the users connected to it and forwards what it receives
+// to their local sessions, so it doesn't matter which instance the sender
+// and the recipient are connected to.
+pub struct ChatHub {
+    registry: Arc<Mutex<Registry>>,
+    redis: ConnectionManager,
+}
+
+impl ChatHub {
+    pub fn start(pubsub: PubSub, redis_url: String, redis: ConnectionManager) -> Self {
+        let (sink, mut stream) = pubsub.split();
+        let registry = Arc::new(Mutex::new(Registry {
+            sessions: HashMap::new(),
+            sink,
+        }));
+
+        let delivery_registry = registry.clone();
+        tokio::spawn(async move {
+            loop {
+                while let Some(msg) = stream.next().await {
+                    let Some(user_id) = msg
+                        .get_channel_name()
+                        .strip_prefix("chat:user:")
+                        .and_then(|id| Uuid::parse_str(id).ok())
+                    else {
+                        continue;
+                    };
+                    let Ok(payload) = msg.get_payload::<String>() else {
+                        continue;
+                    };
+
+                    let registry = delivery_registry.lock().await;
+                    if let Some(sessions) = registry.sessions.get(&user_id) {
+                        for sender in sessions.values() {
+                            let _ = sender.send(payload.clone());
+                        }
+                    }
+                }
+
+                println!("Chat pub/sub connection closed, reconnecting");
+                stream = loop {
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    match resubscribe(&redis_url, &delivery_registry).await {
+                        Ok(stream) => break stream,
+                        Err(e) => println!("Failed to reconnect chat pub/sub: {}", e),
+                    }
+                };
+                println!("Chat pub/sub connection re-established");
+            }
+        });
+
+        ChatHub { registry, redis }
+    }
+
+    // Register a WebSocket session, subscribing to the user's channel when it
+    // is their first session on this instance
+    pub async fn register(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(Uuid, mpsc::UnboundedReceiver<String>), redis::RedisError> {
+        let connection_id = Uuid::new_v4();
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let mut registry = self.registry.lock().await;
+        if !registry.sessions.contains_key(&user_id) {
+            registry.sink.subscribe(user_channel(user_id)).await?;
+        }
+        registry
+            .sessions
+            .entry(user_id)
+            .or_default()
+            .insert(connection_id, sender);
+
+        Ok((connection_id, receiver))
+    }
+
+    pub async fn unregister(&self, user_id: Uuid, connection_id: Uuid) {
+        let mut registry = self.registry.lock().await;
+        let Some(sessions) = registry.sessions.get_mut(&user_id) else {
+            return;
+        };
+        sessions.remove(&connection_id);
+
+        if sessions.is_empty() {
+            registry.sessions.remove(&user_id);
+            if let Err(e) = registry.sink.unsubscribe(user_channel(user_id)).await {
+                println!("Failed to unsubscribe from chat channel: {}", e);
+            }
+        }
+    }
+
+    // Send an event to every session of the user, on any instance
+    pub async fn publish(&self, user_id: Uuid, event: &ServerEvent) {
+        let payload = match serde_json::to_string(event) {
+            Ok(payload) => payload,
+            Err(e) => return println!("Failed to serialize chat event: {}", e),
+        };
+
+        let published: Result<i64, redis::RedisError> = self
+            .redis
+            .clone()
+            .publish(user_channel(user_id), payload)
+            .await;
+        if let Err(e) = published {
+            println!("Failed to publish chat event: {}", e);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use sqlx::{types::Json, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::hub::ServerEvent;
+
+// Replayed per connection, anything older waits for the next one
+const MAX_REPLAYED_EVENTS: i64 = 200;
+
+// Keep an event until one of the user's sessions has received it. The event
+// carries `event_id` so the session can acknowledge it.
+pub async fn store(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    event_id: Uuid,
+    event: &ServerEvent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO inbox_events (id, user_id, payload) VALUES ($1, $2, $3)")
+        .bind(event_id)
+        .bind(user_id)
+        .bind(Json(event))
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+// Events the user hasn't received yet, oldest first
+pub async fn pending(db: &PgPool, user_id: Uuid) -> Result<Vec<ServerEvent>, sqlx::Error> {
+    let events: Vec<Json<ServerEvent>> = sqlx::query_scalar(
+        "SELECT payload FROM inbox_events WHERE user_id = $1 ORDER BY created_at LIMIT $2",
+    )
+    .bind(user_id)
+    .bind(MAX_REPLAYED_EVENTS)
+    .fetch_all(db)
+    .await?;
+
+    Ok(events.into_iter().map(|Json(event)| event).collect())
+}
+
+pub async fn acknowledge(db: &PgPool, user_id: Uuid, event_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM inbox_events WHERE id = $1 AND user_id = $2")
+        .bind(event_id)
+        .bind(user_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,7 @@
+pub mod chat;
+pub mod hub;
+pub mod inbox;
+pub mod unread;
+pub mod ws;
+pub use chat::Chat;
+pub use hub::ChatHub;
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use redis::{aio::ConnectionManager, AsyncCommands};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Counts are kept up to date incrementally, the TTL bounds how long a count
+// can stay off after a race between a new message and a read
+const UNREAD_TTL_SECONDS: i64 = 3600;
+// Marks a hash built from the database, as opposed to one that only holds
+// increments made after it expired
+const LOADED_FIELD: &str = "loaded";
+
+fn unread_key(user_id: Uuid) -> String {
+    format!("unread:{}", user_id)
+}
+
+// Unread messages of the user per conversation, conversations without any
+// are left out
+pub async fn counts(
+    db: &PgPool,
+    redis: &ConnectionManager,
+    user_id: Uuid,
+) -> Result<HashMap<Uuid, i64>, sqlx::Error> {
+    let cached: Result<HashMap<String, i64>, redis::RedisError> =
+        redis.clone().hgetall(unread_key(user_id)).await;
+
+    if let Ok(cached) = cached {
+        if cached.contains_key(LOADED_FIELD) {
+            return Ok(cached
+                .into_iter()
+                .filter_map(|(field, count)| Some((Uuid::parse_str(&field).ok()?, count)))
+                .filter(|(_, count)| *count > 0)
+                .collect());
+        }
+    }
+
+    let rows: Vec<(Uuid, i64)> = sqlx::query_as(
+        "SELECT m.conversation_id, COUNT(*)
+         FROM messages m
+         JOIN conversations c ON c.id = m.conversation_id
+         WHERE (c.user_one_id = $1 OR c.user_two_id = $1)
+           AND m.sender_id <> $1
+           AND m.status <> 'Read'
+         GROUP BY m.conversation_id",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    let key = unread_key(user_id);
+    let mut fields: Vec<(String, i64)> = rows
+        .iter()
+        .map(|(conversation_id, count)| (conversation_id.to_string(), *count))
+        .collect();
+    fields.push((LOADED_FIELD.to_string(), 1));
+
+    let mut redis_conn = redis.clone();
+    let cached: Result<(), redis::RedisError> = redis::pipe()
+        .atomic()
+        .del(&key)
+        .ignore()
+        .hset_multiple(&key, &fields)
+        .ignore()
+        .expire(&key, UNREAD_TTL_SECONDS)
+        .ignore()
+        .query_async(&mut redis_conn)
+        .await;
+    if let Err(e) = cached {
+        println!("Failed to cache unread counts: {}", e);
+    }
+
+    Ok(rows.into_iter().collect())
+}
+
+pub async fn increment(redis: &ConnectionManager, user_id: Uuid, conversation_id: Uuid) {
+    let key = unread_key(user_id);
+    let mut redis_conn = redis.clone();
+    let _: Result<(), redis::RedisError> = redis::pipe()
+        .hincr(&key, conversation_id.to_string(), 1)
+        .ignore()
+        .expire(&key, UNREAD_TTL_SECONDS)
+        .ignore()
+        .query_async(&mut redis_conn)
+        .await;
+}
+
+pub async fn clear(redis: &ConnectionManager, user_id: Uuid, conversation_id: Uuid) {
+    let mut redis_conn = redis.clone();
+    let _: Result<i64, redis::RedisError> = redis_conn
+        .hdel(unread_key(user_id), conversation_id.to_string())
+        .await;
+}
+
+// Drop the cached counts, they are rebuilt on the next read
+pub async fn invalidate(redis: &ConnectionManager, user_ids: &[Uuid]) {
+    let keys: Vec<String> = user_ids.iter().map(|id| unread_key(*id)).collect();
+    let mut redis_conn = redis.clone();
+    let _: Result<i64, redis::RedisError> = redis_conn.del(keys).await;
+}
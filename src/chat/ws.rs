@@ -0,0 +1,265 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    web::{Data, Payload},
+    HttpRequest, HttpResponse, ResponseError,
+};
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, CloseCode, CloseReason, Session};
+use futures_util::StreamExt;
+use redis::aio::ConnectionManager;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::{
+    chat::{mark_delivered, mark_read, pending_messages, send_message, send_typing, ChatError},
+    hub::{ChatHub, ServerEvent},
+    inbox,
+};
+use crate::{
+    auth::{jwt::check_access, AuthenticatedUser},
+    common::AppError,
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+// Drop the connection when the client hasn't answered for this long
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+// Events sent by the client
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientEvent {
+    Message { conversation_id: Uuid, body: String },
+    Typing { conversation_id: Uuid },
+    Read { conversation_id: Uuid },
+}
+
+// GET /api/v1/chat/ws, upgrades to a WebSocket carrying JSON events
+pub async fn connect(
+    req: HttpRequest,
+    body: Payload,
+    db: Data<PgPool>,
+    redis: Data<ConnectionManager>,
+    hub: Data<ChatHub>,
+    auth_user: AuthenticatedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = auth_user.user_id;
+
+    let (connection_id, events) = hub.register(user_id).await.map_err(AppError::from)?;
+
+    let (response, session, stream) = match actix_ws::handle(&req, body) {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            hub.unregister(user_id, connection_id).await;
+            return Err(e);
+        }
+    };
+
+    let stream = stream
+        .max_frame_size(MAX_FRAME_SIZE)
+        .aggregate_continuations();
+
+    actix_web::rt::spawn(async move {
+        run_session(&db, &redis, &hub, &auth_user, session, stream, events).await;
+        hub.unregister(user_id, connection_id).await;
+    });
+
+    Ok(response)
+}
+
+// Ending the login session the socket was opened with closes the socket
+async fn run_session(
+    db: &PgPool,
+    redis: &ConnectionManager,
+    hub: &ChatHub,
+    auth_user: &AuthenticatedUser,
+    mut session: Session,
+    mut stream: AggregatedMessageStream,
+    mut events: mpsc::UnboundedReceiver<String>,
+) {
+    let user_id = auth_user.user_id;
+
+    // The session is subscribed already, so nothing sent from here on can
+    // fall between the replay and live delivery. A message sent meanwhile
+    // may arrive twice, clients dedupe by id.
+    if replay_pending(db, hub, user_id, &mut session)
+        .await
+        .is_err()
+    {
+        let _ = session.close(None).await;
+        return;
+    }
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    loop {
+        tokio::select! {
+            message = stream.next() => {
+                let message = match message {
+                    Some(Ok(message)) => message,
+                    // Client went away or sent a broken frame
+                    _ => break,
+                };
+                last_seen = Instant::now();
+
+                match message {
+                    AggregatedMessage::Text(text) => {
+                        if let Err(e) = handle_client_event(db, redis, hub, user_id, &text).await {
+                            let error = AppError::from(e);
+                            if error.status_code().is_server_error() {
+                                println!("Failed to handle chat event: {}", error);
+                            }
+                            let error = ServerEvent::Error {
+                                message: error.public_message(),
+                            };
+                            if let Ok(error) = serde_json::to_string(&error) {
+                                if session.text(error).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    AggregatedMessage::Ping(bytes) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    AggregatedMessage::Close(reason) => {
+                        let _ = session.close(reason).await;
+                        return;
+                    }
+                    AggregatedMessage::Pong(_) | AggregatedMessage::Binary(_) => {}
+                }
+            }
+            event = events.recv() => {
+                let Some(event) = event else { break };
+                if session.text(event.clone()).await.is_err() {
+                    break;
+                }
+                if let Ok(event) = serde_json::from_str::<ServerEvent>(&event) {
+                    acknowledge(db, hub, user_id, &event).await;
+                }
+            }
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > CLIENT_TIMEOUT || session.ping(b"").await.is_err() {
+                    break;
+                }
+                // The user may have been banned, suspended or signed out
+                // since the socket was opened
+                if let Err(e) = check_access(db, redis, user_id, auth_user.claims.sid).await {
+                    if e.status_code().is_server_error() {
+                        // Can't tell, keep the socket until the next tick
+                        println!("Failed to check chat session access: {}", e);
+                        continue;
+                    }
+                    let reason = CloseReason {
+                        code: CloseCode::Policy,
+                        description: Some(e.public_message()),
+                    };
+                    let _ = session.close(Some(reason)).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    let _ = session.close(None).await;
+}
+
+// Send what the user missed while offline: inbox events, then messages that
+// haven't reached any of their devices
+async fn replay_pending(
+    db: &PgPool,
+    hub: &ChatHub,
+    user_id: Uuid,
+    session: &mut Session,
+) -> Result<(), actix_ws::Closed> {
+    let events = match inbox::pending(db, user_id).await {
+        Ok(events) => events,
+        Err(e) => {
+            println!("Failed to load inbox events: {}", e);
+            Vec::new()
+        }
+    };
+    let messages = match pending_messages(db, user_id).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            println!("Failed to load pending messages: {}", e);
+            Vec::new()
+        }
+    };
+
+    for event in events {
+        send_event(session, &event).await?;
+        acknowledge(db, hub, user_id, &event).await;
+    }
+
+    let mut message_ids = Vec::with_capacity(messages.len());
+    for message in messages {
+        message_ids.push(message.id);
+        send_event(session, &ServerEvent::Message { message }).await?;
+    }
+    if !message_ids.is_empty() {
+        if let Err(e) = mark_delivered(db, hub, user_id, &message_ids).await {
+            println!("Failed to mark messages as delivered: {}", e);
+        }
+    }
+    Ok(())
+}
+
+async fn send_event(session: &mut Session, event: &ServerEvent) -> Result<(), actix_ws::Closed> {
+    match serde_json::to_string(event) {
+        Ok(event) => session.text(event).await,
+        Err(e) => {
+            println!("Failed to serialize chat event: {}", e);
+            Ok(())
+        }
+    }
+}
+
+// Record that an event reached one of the user's devices
+async fn acknowledge(db: &PgPool, hub: &ChatHub, user_id: Uuid, event: &ServerEvent) {
+    let acknowledged = match event {
+        ServerEvent::Message { message } if message.sender_id != user_id => {
+            mark_delivered(db, hub, user_id, &[message.id]).await
+        }
+        _ => match event.inbox_id() {
+            Some(event_id) => inbox::acknowledge(db, user_id, event_id)
+                .await
+                .map_err(ChatError::from),
+            None => Ok(()),
+        },
+    };
+    if let Err(e) = acknowledged {
+        println!("Failed to acknowledge chat event: {}", e);
+    }
+}
+
+async fn handle_client_event(
+    db: &PgPool,
+    redis: &ConnectionManager,
+    hub: &ChatHub,
+    user_id: Uuid,
+    text: &str,
+) -> Result<(), ChatError> {
+    let event: ClientEvent = serde_json::from_str(text)
+        .map_err(|e| ChatError::InvalidMessage(format!("Invalid event: {}", e)))?;
+
+    match event {
+        ClientEvent::Message {
+            conversation_id,
+            body,
+        } => send_message(db, redis, hub, conversation_id, user_id, &body)
+            .await
+            .map(|_| ()),
+        ClientEvent::Typing { conversation_id } => {
+            send_typing(db, hub, conversation_id, user_id).await
+        }
+        ClientEvent::Read { conversation_id } => {
+            mark_read(db, redis, hub, conversation_id, user_id).await
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use clap::{Parser, Subcommand};
+use sqlx::PgPool;
+
+use crate::connections::migrations::{migrate_down, migrate_up, migration_status};
+
+#[derive(Parser, Debug)]
+#[command(name = "amourithm", version)]
+pub struct Cli {
+    // Starts the server when left out
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start the HTTP server
+    Serve,
+    /// Manage the database schema
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MigrateCommand {
+    /// Apply every pending migration
+    Up,
+    /// List migrations and whether they are applied
+    Status,
+    /// Revert the latest migration, or every migration after --target
+    Down {
+        #[arg(long)]
+        target: Option<i64>,
+    },
+}
+
+impl MigrateCommand {
+    pub async fn run(self, db: &PgPool) -> Result<(), String> {
+        match self {
+            MigrateCommand::Up => {
+                migrate_up(db).await.map_err(|e| e.to_string())?;
+                println!("Database schema is up to date");
+            }
+            MigrateCommand::Status => {
+                let status = migration_status(db).await.map_err(|e| e.to_string())?;
+                for migration in status {
+                    let state = match (migration.applied, migration.changed) {
+                        (true, true) => "applied (changed since)",
+                        (true, false) => "applied",
+                        (false, _) => "pending",
+                    };
+                    println!(
+                        "{} {:<30} {}",
+                        migration.version, migration.description, state
+                    );
+                }
+            }
+            MigrateCommand::Down { target } => {
+                let reverted = migrate_down(db, target).await.map_err(|e| e.to_string())?;
+                if reverted.is_empty() {
+                    println!("Nothing to revert");
+                }
+                for version in reverted {
+                    println!("Reverted {}", version);
+                }
+            }
+        }
+        Ok(())
+    }
+}
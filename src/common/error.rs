@@ -1,6 +1,11 @@
-use actix_web::HttpResponse;
+use actix_web::{
+    http::{header::RETRY_AFTER, StatusCode},
+    HttpResponse, ResponseError,
+};
 use serde::Serialize;
 
+use crate::storage::storage::StorageError;
+
 #[derive(Serialize)]
 pub struct ResponseToSend<T> {
     pub success: bool,
@@ -8,34 +13,143 @@ pub struct ResponseToSend<T> {
     pub data: Option<T>,
 }
 
-pub fn handle_bad_request(message: &str) -> HttpResponse {
-    HttpResponse::BadRequest().json(ResponseToSend::<()> {
-        success: false,
-        message: message.to_string(),
-        data: None,
-    })
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub success: bool,
+    // Stable, clients can branch on it
+    pub code: &'static str,
+    pub message: String,
+    pub data: Option<()>,
+}
+
+// Every error a handler can answer with. Messages of the client facing
+// variants are sent as is, everything else is logged and answered with a
+// generic message so no internals leak.
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    // The account exists but its email hasn't been confirmed yet
+    #[error("Email Not Verified")]
+    EmailNotVerified,
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    // A rate limit was hit, `code` tells clients which one
+    #[error("{message}")]
+    TooManyRequests {
+        code: &'static str,
+        message: String,
+        // Seconds until the request may succeed, sent as Retry-After
+        retry_after: Option<i64>,
+    },
+    #[error("{0}")]
+    ServiceUnavailable(String),
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+    #[error("{0}")]
+    Internal(String),
 }
 
-pub fn handle_internal_server_error(message: &str) -> HttpResponse {
-    HttpResponse::InternalServerError().json(ResponseToSend::<()> {
-        success: false,
-        message: message.to_string(),
-        data: None,
-    })
+impl AppError {
+    // Redis being down or slow is reported as a 503, the request can be retried
+    fn is_unavailable(&self) -> bool {
+        match self {
+            AppError::ServiceUnavailable(_) => true,
+            AppError::Redis(e) => {
+                e.is_timeout()
+                    || e.is_io_error()
+                    || e.is_connection_dropped()
+                    || e.is_connection_refusal()
+            }
+            _ => false,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        if self.is_unavailable() {
+            return "service_unavailable";
+        }
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::EmailNotVerified => "email_not_verified",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::TooManyRequests { code, .. } => code,
+            AppError::ServiceUnavailable(_) => "service_unavailable",
+            AppError::Database(_) | AppError::Redis(_) | AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    // What the client is told
+    pub fn public_message(&self) -> String {
+        if let AppError::Redis(_) = self {
+            if self.is_unavailable() {
+                return "Service Temporarily Unavailable".to_string();
+            }
+        }
+        match self {
+            AppError::Database(_) | AppError::Redis(_) | AppError::Internal(_) => {
+                "Internal Server Error".to_string()
+            }
+            _ => self.to_string(),
+        }
+    }
 }
 
-pub fn handle_conflict_error(message: &str) -> HttpResponse {
-    HttpResponse::Conflict().json(ResponseToSend::<()> {
-        success: true,
-        message: message.to_string(),
-        data: None,
-    })
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        if self.is_unavailable() {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) | AppError::EmailNotVerified => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Database(_) | AppError::Redis(_) | AppError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            println!("{}", self);
+        }
+
+        let mut response = HttpResponse::build(status);
+        if let AppError::TooManyRequests {
+            retry_after: Some(seconds),
+            ..
+        } = self
+        {
+            response.insert_header((RETRY_AFTER, seconds.to_string()));
+        }
+        response.json(ErrorResponse {
+            success: false,
+            code: self.code(),
+            message: self.public_message(),
+            data: None,
+        })
+    }
 }
 
-pub fn handle_not_found_error(message: &str) -> HttpResponse {
-    HttpResponse::NotFound().json(ResponseToSend::<()> {
-        success: true,
-        message: message.to_string(),
-        data: None,
-    })
+impl From<StorageError> for AppError {
+    fn from(e: StorageError) -> Self {
+        AppError::Internal(e.to_string())
+    }
 }
@@ -1,2 +1,2 @@
 pub mod error;
-pub use error::*; 
\ No newline at end of file
+pub use error::*;
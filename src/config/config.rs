@@ -0,0 +1,689 @@
+use std::{env, fmt, fs, path::Path, str::FromStr};
+
+use dotenv::dotenv;
+use lettre::message::Mailbox;
+use serde::Deserialize;
+use strum_macros::EnumString;
+
+use crate::{
+    auth::{
+        otp::{OtpConfig, SendLimits},
+        session::SessionConfig,
+    },
+    mailer::smtp::SmtpTls,
+};
+
+// Read when CONFIG_FILE isn't set, skipped if it doesn't exist
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+// Nobody younger may use the platform, whatever the configuration says
+const LEGAL_MINIMUM_AGE: i32 = 18;
+
+// Everything the server can be configured with. Loaded once at startup, in
+// layers: built-in defaults, then the TOML file, then the environment
+// (including `.env`), each overriding the one before.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub database: DatabaseSettings,
+    pub redis: RedisSettings,
+    pub auth: AuthSettings,
+    pub session: SessionConfig,
+    pub otp: OtpConfig,
+    pub mail: MailSettings,
+    pub storage: StorageSettings,
+    pub photos: PhotoSettings,
+    pub accounts: AccountSettings,
+    pub cache: CacheSettings,
+    pub profile: ProfileSettings,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct DatabaseSettings {
+    pub url: String,
+    // Apply pending migrations at startup instead of refusing to start
+    pub run_migrations: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RedisSettings {
+    pub url: String,
+    pub connection_timeout_ms: u64,
+    // Commands fail after this instead of hanging while Redis is down
+    pub response_timeout_ms: u64,
+    // Attempts made each time the connection has to be re-established
+    pub reconnect_retries: usize,
+}
+
+impl Default for RedisSettings {
+    fn default() -> Self {
+        RedisSettings {
+            url: String::new(),
+            connection_timeout_ms: 2000,
+            response_timeout_ms: 1000,
+            reconnect_retries: 3,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuthSettings {
+    // Ed25519 PEM keys named <kid>.pem, an ephemeral key is used when unset
+    pub jwt_keys_dir: Option<String>,
+    pub jwt_active_kid: Option<String>,
+    pub password_reset_ttl_seconds: u64,
+    // Minimum time between two reset emails to the same address
+    pub password_reset_cooldown_seconds: u64,
+    // Maximum number of reset emails per address per day
+    pub password_reset_daily_limit: u32,
+    // Unverified registrations older than this are deleted
+    pub unverified_account_ttl_hours: i32,
+    pub unverified_cleanup_interval_minutes: u64,
+}
+
+impl Default for AuthSettings {
+    fn default() -> Self {
+        AuthSettings {
+            jwt_keys_dir: None,
+            jwt_active_kid: None,
+            password_reset_ttl_seconds: 900,
+            password_reset_cooldown_seconds: 60,
+            password_reset_daily_limit: 3,
+            unverified_account_ttl_hours: 24,
+            unverified_cleanup_interval_minutes: 60,
+        }
+    }
+}
+
+impl AuthSettings {
+    pub fn password_reset_limits(&self) -> SendLimits {
+        SendLimits {
+            scope: "password_reset",
+            cooldown_seconds: self.password_reset_cooldown_seconds,
+            daily_limit: self.password_reset_daily_limit,
+            cooldown_code: "password_reset_cooldown",
+            daily_limit_code: "password_reset_daily_limit",
+        }
+    }
+}
+
+#[derive(Deserialize, EnumString, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum MailTransport {
+    Smtp,
+    // Spool mail to `spool_dir` instead of delivering it
+    #[default]
+    File,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MailSettings {
+    pub transport: MailTransport,
+    pub from: String,
+    pub spool_dir: String,
+    pub max_attempts: u32,
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_tls: SmtpTls,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+}
+
+impl Default for MailSettings {
+    fn default() -> Self {
+        MailSettings {
+            transport: MailTransport::File,
+            from: "Amourithm <no-reply@amourithm.local>".to_string(),
+            spool_dir: "mail_spool".to_string(),
+            max_attempts: 3,
+            smtp_host: None,
+            smtp_port: 587,
+            smtp_tls: SmtpTls::StartTls,
+            smtp_username: None,
+            smtp_password: None,
+        }
+    }
+}
+
+#[derive(Deserialize, EnumString, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Local,
+    S3,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+    // Where uploads are kept with the local backend
+    pub local_dir: String,
+    // Where files only the app hands out, such as data exports, are kept with
+    // the local backend. Never served, keep it outside `local_dir`.
+    pub private_dir: String,
+    // Base URL uploads are served from, derived from the backend when unset
+    pub public_url: Option<String>,
+    pub s3_bucket: Option<String>,
+    // Bucket without public access, the counterpart of `private_dir`
+    pub s3_private_bucket: Option<String>,
+    pub s3_endpoint: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            backend: StorageBackend::Local,
+            local_dir: "media".to_string(),
+            private_dir: "private".to_string(),
+            public_url: None,
+            s3_bucket: None,
+            s3_private_bucket: None,
+            s3_endpoint: None,
+            s3_region: "us-east-1".to_string(),
+            s3_access_key: None,
+            s3_secret_key: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PhotoSettings {
+    // Photos resized at the same time
+    pub workers: usize,
+}
+
+impl Default for PhotoSettings {
+    fn default() -> Self {
+        PhotoSettings { workers: 2 }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AccountSettings {
+    // Deleted accounts are purged after this
+    pub deletion_grace_days: i64,
+    pub purge_interval_minutes: u64,
+    // How long a data export can be downloaded
+    pub data_export_ttl_hours: i32,
+}
+
+impl Default for AccountSettings {
+    fn default() -> Self {
+        AccountSettings {
+            deletion_grace_days: 30,
+            purge_interval_minutes: 60,
+            data_export_ttl_hours: 48,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheSettings {
+    // How long a profile stays cached in Redis
+    pub user_data_ttl_seconds: u64,
+    pub preferences_ttl_seconds: u64,
+    // A ranked feed is reused for this long so paging through it is stable
+    pub discover_feed_ttl_seconds: u64,
+    // How long "not found" is remembered
+    pub negative_ttl_seconds: u64,
+    // Up to this share of the TTL is added at random
+    pub ttl_jitter_percent: u64,
+    // Longest a caller loading a missing entry makes the others wait
+    pub lock_timeout_ms: u64,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            user_data_ttl_seconds: 3600,
+            preferences_ttl_seconds: 3600,
+            discover_feed_ttl_seconds: 600,
+            negative_ttl_seconds: 60,
+            ttl_jitter_percent: 10,
+            lock_timeout_ms: 1000,
+        }
+    }
+}
+
+// Age range allowed on the platform
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProfileSettings {
+    pub min_age: i32,
+    pub max_age: i32,
+}
+
+impl Default for ProfileSettings {
+    fn default() -> Self {
+        ProfileSettings {
+            min_age: LEGAL_MINIMUM_AGE,
+            max_age: 50,
+        }
+    }
+}
+
+// Every problem found while loading, so they can all be fixed in one go
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Settings {
+    pub fn load() -> Result<Self, ConfigError> {
+        dotenv().ok();
+        let mut problems = Vec::new();
+
+        let mut settings = Self::from_file(&mut problems);
+        settings.apply_env(&mut problems);
+        settings.validate(&mut problems);
+
+        if problems.is_empty() {
+            Ok(settings)
+        } else {
+            Err(ConfigError { problems })
+        }
+    }
+
+    // Only what managing the schema needs, so `migrate` runs with nothing
+    // but DATABASE_URL set
+    pub fn load_database() -> Result<DatabaseSettings, ConfigError> {
+        dotenv().ok();
+        let mut problems = Vec::new();
+
+        let mut settings = Self::from_file(&mut problems);
+        settings.apply_database_env(&mut EnvOverrides {
+            problems: &mut problems,
+        });
+        settings.validate_database(&mut problems);
+
+        if problems.is_empty() {
+            Ok(settings.database)
+        } else {
+            Err(ConfigError { problems })
+        }
+    }
+
+    fn from_file(problems: &mut Vec<String>) -> Self {
+        let path = match env::var("CONFIG_FILE") {
+            Ok(path) if !path.is_empty() => path,
+            // Only an explicitly requested file has to exist
+            _ if Path::new(DEFAULT_CONFIG_FILE).exists() => DEFAULT_CONFIG_FILE.to_string(),
+            _ => return Settings::default(),
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                problems.push(format!("Failed to read {}: {}", path, e));
+                return Settings::default();
+            }
+        };
+        toml::from_str(&contents).unwrap_or_else(|e| {
+            problems.push(format!("Failed to parse {}: {}", path, e));
+            Settings::default()
+        })
+    }
+
+    fn apply_env(&mut self, problems: &mut Vec<String>) {
+        let mut env = EnvOverrides { problems };
+
+        env.set("SERVER_HOST", &mut self.server.host);
+        env.set("SERVER_PORT", &mut self.server.port);
+        self.apply_database_env(&mut env);
+        env.set("REDIS_URL", &mut self.redis.url);
+        env.set(
+            "REDIS_CONNECTION_TIMEOUT_MS",
+            &mut self.redis.connection_timeout_ms,
+        );
+        env.set(
+            "REDIS_RESPONSE_TIMEOUT_MS",
+            &mut self.redis.response_timeout_ms,
+        );
+        env.set("REDIS_RECONNECT_RETRIES", &mut self.redis.reconnect_retries);
+
+        env.set_opt("JWT_KEYS_DIR", &mut self.auth.jwt_keys_dir);
+        env.set_opt("JWT_ACTIVE_KID", &mut self.auth.jwt_active_kid);
+        env.set(
+            "PASSWORD_RESET_TTL_SECONDS",
+            &mut self.auth.password_reset_ttl_seconds,
+        );
+        env.set(
+            "PASSWORD_RESET_COOLDOWN_SECONDS",
+            &mut self.auth.password_reset_cooldown_seconds,
+        );
+        env.set(
+            "PASSWORD_RESET_DAILY_LIMIT",
+            &mut self.auth.password_reset_daily_limit,
+        );
+        env.set(
+            "UNVERIFIED_ACCOUNT_TTL_HOURS",
+            &mut self.auth.unverified_account_ttl_hours,
+        );
+        env.set(
+            "UNVERIFIED_CLEANUP_INTERVAL_MINUTES",
+            &mut self.auth.unverified_cleanup_interval_minutes,
+        );
+        env.set(
+            "ACCESS_TOKEN_TTL_MINUTES",
+            &mut self.session.access_token_ttl_minutes,
+        );
+        env.set(
+            "REFRESH_TOKEN_TTL_DAYS",
+            &mut self.session.refresh_token_ttl_days,
+        );
+
+        env.set("OTP_TTL_SECONDS", &mut self.otp.ttl_seconds);
+        env.set(
+            "OTP_RESEND_COOLDOWN_SECONDS",
+            &mut self.otp.resend_cooldown_seconds,
+        );
+        env.set("OTP_DAILY_LIMIT", &mut self.otp.daily_limit);
+        env.set("OTP_MAX_ATTEMPTS", &mut self.otp.max_attempts);
+
+        env.set("MAIL_TRANSPORT", &mut self.mail.transport);
+        env.set("MAIL_FROM", &mut self.mail.from);
+        env.set("MAIL_SPOOL_DIR", &mut self.mail.spool_dir);
+        env.set("MAIL_MAX_ATTEMPTS", &mut self.mail.max_attempts);
+        env.set_opt("SMTP_HOST", &mut self.mail.smtp_host);
+        env.set("SMTP_PORT", &mut self.mail.smtp_port);
+        env.set("SMTP_TLS", &mut self.mail.smtp_tls);
+        env.set_opt("SMTP_USERNAME", &mut self.mail.smtp_username);
+        env.set_opt("SMTP_PASSWORD", &mut self.mail.smtp_password);
+
+        env.set("STORAGE_BACKEND", &mut self.storage.backend);
+        env.set("STORAGE_LOCAL_DIR", &mut self.storage.local_dir);
+        env.set("STORAGE_PRIVATE_DIR", &mut self.storage.private_dir);
+        env.set_opt("STORAGE_PUBLIC_URL", &mut self.storage.public_url);
+        env.set_opt("S3_BUCKET", &mut self.storage.s3_bucket);
+        env.set_opt("S3_PRIVATE_BUCKET", &mut self.storage.s3_private_bucket);
+        env.set_opt("S3_ENDPOINT", &mut self.storage.s3_endpoint);
+        env.set("S3_REGION", &mut self.storage.s3_region);
+        env.set_opt("S3_ACCESS_KEY", &mut self.storage.s3_access_key);
+        env.set_opt("S3_SECRET_KEY", &mut self.storage.s3_secret_key);
+
+        env.set("PHOTO_WORKERS", &mut self.photos.workers);
+
+        env.set(
+            "ACCOUNT_DELETION_GRACE_DAYS",
+            &mut self.accounts.deletion_grace_days,
+        );
+        env.set(
+            "ACCOUNT_PURGE_INTERVAL_MINUTES",
+            &mut self.accounts.purge_interval_minutes,
+        );
+        env.set(
+            "DATA_EXPORT_TTL_HOURS",
+            &mut self.accounts.data_export_ttl_hours,
+        );
+
+        env.set(
+            "USER_CACHE_TTL_SECONDS",
+            &mut self.cache.user_data_ttl_seconds,
+        );
+        env.set(
+            "PREFERENCES_CACHE_TTL_SECONDS",
+            &mut self.cache.preferences_ttl_seconds,
+        );
+        env.set(
+            "DISCOVER_FEED_TTL_SECONDS",
+            &mut self.cache.discover_feed_ttl_seconds,
+        );
+        env.set(
+            "CACHE_NEGATIVE_TTL_SECONDS",
+            &mut self.cache.negative_ttl_seconds,
+        );
+        env.set(
+            "CACHE_TTL_JITTER_PERCENT",
+            &mut self.cache.ttl_jitter_percent,
+        );
+        env.set("CACHE_LOCK_TIMEOUT_MS", &mut self.cache.lock_timeout_ms);
+
+        env.set("MINIMUM_AGE", &mut self.profile.min_age);
+        env.set("MAXIMUM_AGE", &mut self.profile.max_age);
+    }
+
+    fn apply_database_env(&mut self, env: &mut EnvOverrides) {
+        env.set("DATABASE_URL", &mut self.database.url);
+        env.set("DATABASE_RUN_MIGRATIONS", &mut self.database.run_migrations);
+    }
+
+    fn validate_database(&self, problems: &mut Vec<String>) {
+        if self.database.url.is_empty() {
+            problems.push("DATABASE_URL must be set".to_string());
+        }
+    }
+
+    fn validate(&self, problems: &mut Vec<String>) {
+        self.validate_database(problems);
+
+        let mut check = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_string());
+            }
+        };
+
+        check(!self.server.host.is_empty(), "SERVER_HOST must be set");
+        check(self.server.port > 0, "SERVER_PORT must be greater than 0");
+        check(!self.redis.url.is_empty(), "REDIS_URL must be set");
+        check(
+            self.redis.connection_timeout_ms > 0,
+            "REDIS_CONNECTION_TIMEOUT_MS must be greater than 0",
+        );
+        check(
+            self.redis.response_timeout_ms > 0,
+            "REDIS_RESPONSE_TIMEOUT_MS must be greater than 0",
+        );
+
+        check(
+            self.auth.password_reset_ttl_seconds > 0,
+            "PASSWORD_RESET_TTL_SECONDS must be greater than 0",
+        );
+        check(
+            self.auth.password_reset_daily_limit > 0,
+            "PASSWORD_RESET_DAILY_LIMIT must be greater than 0",
+        );
+        check(
+            self.auth.unverified_account_ttl_hours > 0,
+            "UNVERIFIED_ACCOUNT_TTL_HOURS must be greater than 0",
+        );
+        check(
+            self.auth.unverified_cleanup_interval_minutes > 0,
+            "UNVERIFIED_CLEANUP_INTERVAL_MINUTES must be greater than 0",
+        );
+        check(
+            self.session.access_token_ttl_minutes > 0,
+            "ACCESS_TOKEN_TTL_MINUTES must be greater than 0",
+        );
+        check(
+            self.session.refresh_token_ttl_days > 0,
+            "REFRESH_TOKEN_TTL_DAYS must be greater than 0",
+        );
+
+        check(
+            self.otp.ttl_seconds > 0,
+            "OTP_TTL_SECONDS must be greater than 0",
+        );
+        check(
+            self.otp.daily_limit > 0,
+            "OTP_DAILY_LIMIT must be greater than 0",
+        );
+        check(
+            self.otp.max_attempts > 0,
+            "OTP_MAX_ATTEMPTS must be greater than 0",
+        );
+
+        check(
+            self.mail.from.parse::<Mailbox>().is_ok(),
+            "MAIL_FROM must be a valid mailbox",
+        );
+        check(
+            self.mail.max_attempts > 0,
+            "MAIL_MAX_ATTEMPTS must be greater than 0",
+        );
+        if self.mail.transport == MailTransport::Smtp {
+            check(
+                is_set(&self.mail.smtp_host),
+                "SMTP_HOST must be set when MAIL_TRANSPORT is smtp",
+            );
+        }
+
+        if self.storage.backend == StorageBackend::Local {
+            check(
+                !Path::new(&self.storage.private_dir).starts_with(&self.storage.local_dir)
+                    && !Path::new(&self.storage.local_dir).starts_with(&self.storage.private_dir),
+                "STORAGE_PRIVATE_DIR must be outside STORAGE_LOCAL_DIR",
+            );
+        }
+        if self.storage.backend == StorageBackend::S3 {
+            check(
+                is_set(&self.storage.s3_bucket),
+                "S3_BUCKET must be set when STORAGE_BACKEND is s3",
+            );
+            check(
+                is_set(&self.storage.s3_private_bucket)
+                    && self.storage.s3_private_bucket != self.storage.s3_bucket,
+                "S3_PRIVATE_BUCKET must be set to a bucket other than S3_BUCKET when STORAGE_BACKEND is s3",
+            );
+            check(
+                is_set(&self.storage.s3_endpoint),
+                "S3_ENDPOINT must be set when STORAGE_BACKEND is s3",
+            );
+            check(
+                is_set(&self.storage.s3_access_key),
+                "S3_ACCESS_KEY must be set when STORAGE_BACKEND is s3",
+            );
+            check(
+                is_set(&self.storage.s3_secret_key),
+                "S3_SECRET_KEY must be set when STORAGE_BACKEND is s3",
+            );
+        }
+
+        check(
+            self.photos.workers > 0,
+            "PHOTO_WORKERS must be greater than 0",
+        );
+        check(
+            self.accounts.deletion_grace_days >= 0,
+            "ACCOUNT_DELETION_GRACE_DAYS must not be negative",
+        );
+        check(
+            self.accounts.purge_interval_minutes > 0,
+            "ACCOUNT_PURGE_INTERVAL_MINUTES must be greater than 0",
+        );
+        check(
+            self.accounts.data_export_ttl_hours > 0,
+            "DATA_EXPORT_TTL_HOURS must be greater than 0",
+        );
+        check(
+            self.cache.user_data_ttl_seconds > 0,
+            "USER_CACHE_TTL_SECONDS must be greater than 0",
+        );
+        check(
+            self.cache.preferences_ttl_seconds > 0,
+            "PREFERENCES_CACHE_TTL_SECONDS must be greater than 0",
+        );
+        check(
+            self.cache.discover_feed_ttl_seconds > 0,
+            "DISCOVER_FEED_TTL_SECONDS must be greater than 0",
+        );
+        check(
+            self.cache.negative_ttl_seconds > 0,
+            "CACHE_NEGATIVE_TTL_SECONDS must be greater than 0",
+        );
+        check(
+            self.cache.ttl_jitter_percent <= 100,
+            "CACHE_TTL_JITTER_PERCENT must be at most 100",
+        );
+        check(
+            self.cache.lock_timeout_ms > 0,
+            "CACHE_LOCK_TIMEOUT_MS must be greater than 0",
+        );
+
+        check(
+            self.profile.min_age >= LEGAL_MINIMUM_AGE,
+            &format!("MINIMUM_AGE must be at least {}", LEGAL_MINIMUM_AGE),
+        );
+        check(
+            self.profile.min_age <= self.profile.max_age,
+            "MINIMUM_AGE must not be greater than MAXIMUM_AGE",
+        );
+    }
+
+    // Base URL the server is reachable at, for links to itself
+    pub fn server_url(&self) -> String {
+        format!("http://{}:{}", self.server.host, self.server.port)
+    }
+}
+
+fn is_set(value: &Option<String>) -> bool {
+    value.as_deref().is_some_and(|value| !value.is_empty())
+}
+
+// Environment variables override whatever was set before them. Empty values
+// count as unset, so a blank line in `.env` keeps the default.
+struct EnvOverrides<'a> {
+    problems: &'a mut Vec<String>,
+}
+
+impl EnvOverrides<'_> {
+    fn set<T: FromStr>(&mut self, key: &str, target: &mut T) {
+        let Some(value) = read_env(key) else {
+            return;
+        };
+        match value.parse() {
+            Ok(value) => *target = value,
+            Err(_) => self
+                .problems
+                .push(format!("{} has an invalid value: {}", key, value)),
+        }
+    }
+
+    fn set_opt(&mut self, key: &str, target: &mut Option<String>) {
+        if let Some(value) = read_env(key) {
+            *target = Some(value);
+        }
+    }
+}
+
+fn read_env(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.is_empty())
+}
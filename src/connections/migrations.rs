@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use sqlx::{
+    migrate::{Migrate, MigrateError, Migrator},
+    PgPool,
+};
+
+// Every file in `migrations/`, embedded at build time
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+    // Applied, but the file has been edited since
+    pub changed: bool,
+}
+
+// Every embedded migration and whether the database has it
+pub async fn migration_status(db: &PgPool) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let mut conn = db.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    if let Some(version) = conn.dirty_version().await? {
+        return Err(MigrateError::Dirty(version));
+    }
+
+    let applied: HashMap<i64, Vec<u8>> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum.into_owned()))
+        .collect();
+
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| {
+            let checksum = applied.get(&migration.version);
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                applied: checksum.is_some(),
+                changed: checksum.is_some_and(|checksum| **checksum != *migration.checksum),
+            }
+        })
+        .collect())
+}
+
+// Apply every pending migration
+pub async fn migrate_up(db: &PgPool) -> Result<(), MigrateError> {
+    MIGRATOR.run(db).await
+}
+
+// Revert applied migrations down to `target`, or only the latest one when no
+// target is given. Returns the versions that were reverted.
+pub async fn migrate_down(db: &PgPool, target: Option<i64>) -> Result<Vec<i64>, MigrateError> {
+    let applied: Vec<i64> = migration_status(db)
+        .await?
+        .into_iter()
+        .filter(|migration| migration.applied)
+        .map(|migration| migration.version)
+        .collect();
+
+    let target = match target {
+        Some(target) => target,
+        None => match applied.len() {
+            0 => return Ok(Vec::new()),
+            len if len > 1 => applied[len - 2],
+            _ => 0,
+        },
+    };
+
+    MIGRATOR.undo(db, target).await?;
+
+    Ok(applied
+        .into_iter()
+        .rev()
+        .filter(|version| *version > target)
+        .collect())
+}
+
+// Called at startup. Applies pending migrations when `run_migrations` is on,
+// otherwise refuses to serve on a schema that is behind the binary.
+pub async fn ensure_schema(db: &PgPool, run_migrations: bool) -> Result<(), String> {
+    if run_migrations {
+        return migrate_up(db)
+            .await
+            .map_err(|e| format!("Failed to run migrations: {}", e));
+    }
+
+    let status = migration_status(db)
+        .await
+        .map_err(|e| format!("Failed to read migration status: {}", e))?;
+    let pending: Vec<String> = status
+        .iter()
+        .filter(|migration| !migration.applied)
+        .map(|migration| format!("{} {}", migration.version, migration.description))
+        .collect();
+
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Database schema is behind, pending migrations: {}. Run `amourithm migrate up` or set DATABASE_RUN_MIGRATIONS=true",
+            pending.join(", ")
+        ))
+    }
+}
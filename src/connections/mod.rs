@@ -1,4 +1,5 @@
 pub mod database;
+pub mod migrations;
 pub mod redis;
 pub use database::database_connection;
-pub use redis::connect_to_redis;
\ No newline at end of file
+pub use redis::{connect_to_redis, connect_to_redis_pubsub};
@@ -1,17 +1,27 @@
-use redis::{aio::MultiplexedConnection, Client, RedisError};
-use std::env;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
 
-pub async fn connect_to_redis() -> Result<Arc<Mutex<MultiplexedConnection>>, RedisError> {
-    // Get the Redis URL from environment variables
-    let redis_url = env::var("REDIS_URL").expect("REDIS_URL must be set in .env file");
+use redis::{
+    aio::{ConnectionManager, ConnectionManagerConfig, PubSub},
+    Client, RedisError,
+};
 
-    // Create a Redis client
-    let client = Client::open(redis_url).expect("Failed to create Redis client");
+use crate::config::config::RedisSettings;
 
-    // Use the recommended multiplexed connection
-    let connection = client.get_multiplexed_async_connection().await?;
+// The manager is cheap to clone and shares one multiplexed connection, which
+// it re-establishes in the background after Redis restarts
+pub async fn connect_to_redis(settings: &RedisSettings) -> Result<ConnectionManager, RedisError> {
+    let client = Client::open(settings.url.as_str())?;
 
-    Ok(Arc::new(Mutex::new(connection)))
+    let config = ConnectionManagerConfig::new()
+        .set_connection_timeout(Duration::from_millis(settings.connection_timeout_ms))
+        .set_response_timeout(Duration::from_millis(settings.response_timeout_ms))
+        .set_number_of_retries(settings.reconnect_retries);
+
+    ConnectionManager::new_with_config(client, config).await
+}
+
+// Subscriptions need a connection of their own
+pub async fn connect_to_redis_pubsub(redis_url: &str) -> Result<PubSub, RedisError> {
+    let client = Client::open(redis_url)?;
+    client.get_async_pubsub().await
 }
@@ -0,0 +1,350 @@
+use std::cmp::Ordering;
+
+use actix_web::{
+    web::{Data, Query},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool};
+use uuid::Uuid;
+
+use super::scorer::{Candidate, Scorer, Viewer};
+use crate::{
+    auth::AuthenticatedUser,
+    cache::{Cache, CacheKey},
+    common::{AppError, ResponseToSend},
+    config::{config::ProfileSettings, Settings},
+    interests::Interests,
+    preferences::Preferences,
+    user::User,
+};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 50;
+// How many candidates are ranked each time the feed is rebuilt
+const CANDIDATE_POOL_SIZE: i64 = 500;
+
+#[derive(Deserialize, Debug)]
+pub struct DiscoverQuery {
+    // `next_cursor` of the previous page, the first page when left out
+    cursor: Option<String>,
+    limit: Option<usize>,
+    // Comma separated interest slugs, only profiles sharing one are shown
+    interests: Option<String>,
+}
+
+#[derive(FromRow)]
+struct CandidateRow {
+    user_id: Uuid,
+    #[sqlx(flatten)]
+    profile: User,
+    distance_km: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DiscoverProfile {
+    user_id: Uuid,
+    #[serde(flatten)]
+    profile: User,
+    distance_km: Option<i32>,
+    score: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DiscoverPage<'a> {
+    profiles: &'a [DiscoverProfile],
+    limit: usize,
+    // Set while there are more profiles to fetch
+    next_cursor: Option<String>,
+}
+
+pub struct Discovery;
+
+impl Discovery {
+    // Get Discover Feed. Pages are cursor based, swipes remove profiles from
+    // the feed so an offset would skip people.
+    pub async fn discover(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        scorer: Data<dyn Scorer>,
+        settings: Data<Settings>,
+        auth_user: AuthenticatedUser,
+        query: Query<DiscoverQuery>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let cursor = match query.cursor.as_deref() {
+            Some(cursor) => Some(
+                parse_cursor(cursor)
+                    .ok_or_else(|| AppError::BadRequest("Invalid Cursor".to_string()))?,
+            ),
+            None => None,
+        };
+
+        let mut feed = Self::get_feed(&db, &cache, &**scorer, &settings.profile, user_id).await?;
+
+        // Filtering happens on the cached feed so every filter shares one ranking
+        let wanted_interests: Vec<&str> = query
+            .interests
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|slug| !slug.is_empty())
+            .collect();
+        if !wanted_interests.is_empty() {
+            feed.retain(|profile| {
+                profile
+                    .profile
+                    .interests
+                    .iter()
+                    .any(|interest| wanted_interests.contains(&interest.slug.as_str()))
+            });
+        }
+
+        // Everything ranked up to the cursor has been served already
+        let start = match cursor {
+            Some(cursor) => feed
+                .partition_point(|profile| feed_order(profile.rank(), cursor) != Ordering::Greater),
+            None => 0,
+        };
+        let end = (start + limit).min(feed.len());
+        let profiles = &feed[start..end];
+        let next_cursor = if end < feed.len() {
+            profiles.last().map(|profile| encode_cursor(profile.rank()))
+        } else {
+            None
+        };
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Discover Feed Fetch Successfully".to_string(),
+            data: Some(DiscoverPage {
+                profiles,
+                limit,
+                next_cursor,
+            }),
+        }))
+    }
+
+    // Return the user's ranked feed from Redis, building it on a cache miss
+    async fn get_feed(
+        db: &PgPool,
+        cache: &Cache,
+        scorer: &dyn Scorer,
+        profile: &ProfileSettings,
+        user_id: Uuid,
+    ) -> Result<Vec<DiscoverProfile>, AppError> {
+        let mut feed = cache
+            .get_or_load(CacheKey::DiscoverFeed(user_id), || async {
+                Self::build_feed(db, cache, scorer, profile, user_id)
+                    .await
+                    .map(Some)
+            })
+            .await?
+            .unwrap_or_default();
+
+        // Someone may have been suspended or banned since the feed was built
+        let feed_ids: Vec<Uuid> = feed.iter().map(|profile| profile.user_id).collect();
+        let restricted_ids = restricted_users(db, &feed_ids).await?;
+        feed.retain(|profile| !restricted_ids.contains(&profile.user_id));
+
+        Ok(feed)
+    }
+
+    async fn build_feed(
+        db: &PgPool,
+        cache: &Cache,
+        scorer: &dyn Scorer,
+        profile: &ProfileSettings,
+        user_id: Uuid,
+    ) -> Result<Vec<DiscoverProfile>, AppError> {
+        let preferences = Preferences::for_user(db, cache, profile, user_id).await?;
+        let wanted_genders: Vec<String> = preferences
+            .wanted_genders
+            .iter()
+            .map(|gender| gender.to_string())
+            .collect();
+
+        let viewer = Self::load_viewer(db, user_id, &preferences).await?;
+
+        // Skip people already liked or passed, blocks either way, suspended or
+        // banned accounts, and anyone outside the viewer's age range, wanted
+        // genders or maximum distance. The distance limit only applies once the
+        // viewer has set a location.
+        let rows = sqlx::query_as::<_, CandidateRow>(
+            "SELECT u.id AS user_id, ud.firstname, ud.lastname, ud.age, ud.gender, ud.bio, ud.city, ud.profile_picture_url,
+                    ROUND(earth_distance(ll_to_earth(me.latitude, me.longitude), ll_to_earth(ud.latitude, ud.longitude)) / 1000)::INT AS distance_km
+             FROM users u
+             JOIN usersdata ud ON ud.user_id = u.id
+             LEFT JOIN usersdata me ON me.user_id = $1
+             WHERE u.id <> $1
+               AND u.email_verified_at IS NOT NULL
+               AND u.status = 'Active' AND (u.suspended_until IS NULL OR u.suspended_until <= CURRENT_TIMESTAMP)
+               AND NOT EXISTS (SELECT 1 FROM likes l WHERE l.liker_id = $1 AND l.likee_id = u.id)
+               AND NOT EXISTS (SELECT 1 FROM blocks b WHERE (b.blocker_id = $1 AND b.blocked_id = u.id) OR (b.blocker_id = u.id AND b.blocked_id = $1))
+               AND ud.age BETWEEN $3 AND $4
+               AND (cardinality($5::TEXT[]) = 0 OR ud.gender = ANY($5))
+               AND ($6::FLOAT8 IS NULL OR me.latitude IS NULL OR me.longitude IS NULL
+                    OR (earth_box(ll_to_earth(me.latitude, me.longitude), $6) @> ll_to_earth(ud.latitude, ud.longitude)
+                        AND earth_distance(ll_to_earth(me.latitude, me.longitude), ll_to_earth(ud.latitude, ud.longitude)) <= $6))
+             ORDER BY ud.updated_at DESC
+             LIMIT $2",
+        )
+        .bind(user_id)
+        .bind(CANDIDATE_POOL_SIZE)
+        .bind(preferences.min_age)
+        .bind(preferences.max_age)
+        .bind(&wanted_genders)
+        .bind(
+            preferences
+                .max_distance_km
+                .map(|distance| distance as f64 * 1000.0),
+        )
+        .fetch_all(db)
+        .await?;
+
+        let candidate_ids: Vec<Uuid> = rows.iter().map(|row| row.user_id).collect();
+        let mut interests = Interests::for_users(db, &candidate_ids).await?;
+
+        let mut feed: Vec<DiscoverProfile> = rows
+            .into_iter()
+            .map(|mut row| {
+                row.profile.interests = interests.remove(&row.user_id).unwrap_or_default();
+                let candidate = Candidate {
+                    user_id: row.user_id,
+                    interests: interest_slugs(&row.profile),
+                    profile: row.profile,
+                    distance_km: row.distance_km,
+                };
+                DiscoverProfile {
+                    score: scorer.score(&viewer, &candidate),
+                    user_id: candidate.user_id,
+                    profile: candidate.profile,
+                    distance_km: candidate.distance_km,
+                }
+            })
+            .collect();
+
+        feed.sort_by(|a, b| feed_order(a.rank(), b.rank()));
+        Ok(feed)
+    }
+
+    async fn load_viewer(
+        db: &PgPool,
+        user_id: Uuid,
+        preferences: &Preferences,
+    ) -> Result<Viewer, sqlx::Error> {
+        let mut profile = sqlx::query_as::<_, User>(
+            "SELECT firstname, lastname, age, gender, bio, city, profile_picture_url FROM usersdata WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?
+        .unwrap_or_default();
+        profile.interests = Interests::for_user(db, user_id).await?;
+
+        Ok(Viewer {
+            interests: interest_slugs(&profile),
+            profile,
+            wanted_genders: preferences.wanted_genders.clone(),
+        })
+    }
+}
+
+// The given users who are currently suspended or banned
+async fn restricted_users(db: &PgPool, user_ids: &[Uuid]) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT id FROM users
+         WHERE id = ANY($1) AND (status <> 'Active' OR suspended_until > CURRENT_TIMESTAMP)",
+    )
+    .bind(user_ids)
+    .fetch_all(db)
+    .await
+}
+
+fn interest_slugs(profile: &User) -> Vec<String> {
+    profile
+        .interests
+        .iter()
+        .map(|interest| interest.slug.clone())
+        .collect()
+}
+
+impl DiscoverProfile {
+    fn rank(&self) -> (f64, Uuid) {
+        (self.score, self.user_id)
+    }
+}
+
+// Best score first, ties broken by user id so the order is total
+fn feed_order(a: (f64, Uuid), b: (f64, Uuid)) -> Ordering {
+    b.0.total_cmp(&a.0).then(a.1.cmp(&b.1))
+}
+
+// Cursors point at the last profile served, `<score>:<user_id>`
+fn encode_cursor((score, user_id): (f64, Uuid)) -> String {
+    format!("{}:{}", score, user_id)
+}
+
+fn parse_cursor(cursor: &str) -> Option<(f64, Uuid)> {
+    let (score, user_id) = cursor.split_once(':')?;
+    let score: f64 = score.parse().ok()?;
+    if !score.is_finite() {
+        return None;
+    }
+    Some((score, Uuid::parse_str(user_id).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let rank = (0.123456789, Uuid::new_v4());
+        assert_eq!(parse_cursor(&encode_cursor(rank)), Some(rank));
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        assert_eq!(parse_cursor(""), None);
+        assert_eq!(parse_cursor("0.5"), None);
+        assert_eq!(
+            parse_cursor("NaN:00000000-0000-0000-0000-000000000000"),
+            None
+        );
+        assert_eq!(parse_cursor("0.5:not-a-uuid"), None);
+    }
+
+    #[test]
+    fn feed_is_ordered_by_score_then_user_id() {
+        let low = Uuid::from_u128(1);
+        let high = Uuid::from_u128(2);
+
+        assert_eq!(feed_order((0.9, high), (0.5, low)), Ordering::Less);
+        assert_eq!(feed_order((0.5, low), (0.5, high)), Ordering::Less);
+        assert_eq!(feed_order((0.5, low), (0.5, low)), Ordering::Equal);
+    }
+
+    #[test]
+    fn next_page_starts_after_the_cursor_when_profiles_were_removed() {
+        let ids: Vec<Uuid> = (1..=5).map(Uuid::from_u128).collect();
+        let mut feed: Vec<(f64, Uuid)> = vec![
+            (0.9, ids[0]),
+            (0.8, ids[1]),
+            (0.7, ids[2]),
+            (0.6, ids[3]),
+            (0.5, ids[4]),
+        ];
+        // The first page ended at ids[1], which was then swiped away
+        let cursor = feed[1];
+        feed.remove(1);
+
+        let start = feed.partition_point(|rank| feed_order(*rank, cursor) != Ordering::Greater);
+        assert_eq!(feed[start], (0.7, ids[2]));
+    }
+}
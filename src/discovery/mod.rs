@@ -0,0 +1,3 @@
+pub mod discovery;
+pub mod scorer;
+pub use discovery::Discovery;
@@ -0,0 +1,286 @@
+use std::collections::HashSet;
+
+use uuid::Uuid;
+
+use crate::user::{Gender, User};
+
+// The user asking for candidates, with what they are looking for
+#[derive(Debug)]
+pub struct Viewer {
+    pub profile: User,
+    // Genders the viewer wants to see, empty means any
+    pub wanted_genders: Vec<Gender>,
+    pub interests: Vec<String>,
+}
+
+// A profile that may be shown to the viewer
+#[derive(Debug)]
+pub struct Candidate {
+    pub user_id: Uuid,
+    pub profile: User,
+    pub interests: Vec<String>,
+    // Unknown when either user hasn't set a location
+    pub distance_km: Option<i32>,
+}
+
+// Ranks candidates for a viewer. Scores are in 0.0..=1.0, higher is better.
+pub trait Scorer: Send + Sync {
+    fn score(&self, viewer: &Viewer, candidate: &Candidate) -> f64;
+}
+
+// Closer ages score higher, fading out over `max_gap` years
+pub struct AgeScorer {
+    pub max_gap: i32,
+}
+
+impl Scorer for AgeScorer {
+    fn score(&self, viewer: &Viewer, candidate: &Candidate) -> f64 {
+        match (viewer.profile.age, candidate.profile.age) {
+            (Some(a), Some(b)) => {
+                let gap = (a - b).abs().min(self.max_gap);
+                1.0 - gap as f64 / self.max_gap as f64
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+// Closer candidates score higher, fading out over `max_km` kilometres
+pub struct DistanceScorer {
+    pub max_km: i32,
+}
+
+impl Scorer for DistanceScorer {
+    fn score(&self, _viewer: &Viewer, candidate: &Candidate) -> f64 {
+        match candidate.distance_km {
+            Some(distance) => 1.0 - distance.clamp(0, self.max_km) as f64 / self.max_km as f64,
+            None => 0.0,
+        }
+    }
+}
+
+// Living in the same city
+pub struct CityScorer;
+
+impl Scorer for CityScorer {
+    fn score(&self, viewer: &Viewer, candidate: &Candidate) -> f64 {
+        match (&viewer.profile.city, &candidate.profile.city) {
+            (Some(a), Some(b)) if a.trim().eq_ignore_ascii_case(b.trim()) => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
+// Whether the candidate's gender is one the viewer wants to see
+pub struct GenderPreferenceScorer;
+
+impl Scorer for GenderPreferenceScorer {
+    fn score(&self, viewer: &Viewer, candidate: &Candidate) -> f64 {
+        if viewer.wanted_genders.is_empty() {
+            return 1.0;
+        }
+        match candidate.profile.gender {
+            Some(gender) if viewer.wanted_genders.contains(&gender) => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
+// Share of interests the two users have in common (Jaccard index)
+pub struct SharedInterestsScorer;
+
+impl Scorer for SharedInterestsScorer {
+    fn score(&self, viewer: &Viewer, candidate: &Candidate) -> f64 {
+        let mine: HashSet<&String> = viewer.interests.iter().collect();
+        let theirs: HashSet<&String> = candidate.interests.iter().collect();
+        let union = mine.union(&theirs).count();
+        if union == 0 {
+            return 0.0;
+        }
+        mine.intersection(&theirs).count() as f64 / union as f64
+    }
+}
+
+// Weighted average of other scorers
+#[derive(Default)]
+pub struct WeightedScorer {
+    scorers: Vec<(f64, Box<dyn Scorer>)>,
+}
+
+impl WeightedScorer {
+    pub fn with(mut self, weight: f64, scorer: impl Scorer + 'static) -> Self {
+        self.scorers.push((weight, Box::new(scorer)));
+        self
+    }
+}
+
+impl Scorer for WeightedScorer {
+    fn score(&self, viewer: &Viewer, candidate: &Candidate) -> f64 {
+        let total_weight: f64 = self.scorers.iter().map(|(weight, _)| weight).sum();
+        if total_weight == 0.0 {
+            return 0.0;
+        }
+        self.scorers
+            .iter()
+            .map(|(weight, scorer)| weight * scorer.score(viewer, candidate))
+            .sum::<f64>()
+            / total_weight
+    }
+}
+
+pub fn default_scorer() -> WeightedScorer {
+    WeightedScorer::default()
+        .with(3.0, GenderPreferenceScorer)
+        .with(2.0, SharedInterestsScorer)
+        .with(1.5, AgeScorer { max_gap: 15 })
+        .with(1.5, DistanceScorer { max_km: 100 })
+        .with(1.0, CityScorer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewer(age: Option<i32>, city: Option<&str>, wanted_genders: Vec<Gender>) -> Viewer {
+        Viewer {
+            profile: User {
+                age,
+                city: city.map(str::to_string),
+                ..User::default()
+            },
+            wanted_genders,
+            interests: Vec::new(),
+        }
+    }
+
+    fn candidate(age: Option<i32>, gender: Option<Gender>, distance_km: Option<i32>) -> Candidate {
+        Candidate {
+            user_id: Uuid::new_v4(),
+            profile: User {
+                age,
+                gender,
+                ..User::default()
+            },
+            interests: Vec::new(),
+            distance_km,
+        }
+    }
+
+    fn interests(slugs: &[&str]) -> Vec<String> {
+        slugs.iter().map(|slug| slug.to_string()).collect()
+    }
+
+    #[test]
+    fn age_score_fades_out_over_max_gap() {
+        let scorer = AgeScorer { max_gap: 10 };
+        let viewer = viewer(Some(30), None, Vec::new());
+
+        assert_eq!(scorer.score(&viewer, &candidate(Some(30), None, None)), 1.0);
+        assert_eq!(scorer.score(&viewer, &candidate(Some(25), None, None)), 0.5);
+        assert_eq!(scorer.score(&viewer, &candidate(Some(35), None, None)), 0.5);
+        assert_eq!(scorer.score(&viewer, &candidate(Some(60), None, None)), 0.0);
+        assert_eq!(scorer.score(&viewer, &candidate(None, None, None)), 0.0);
+    }
+
+    #[test]
+    fn distance_score_fades_out_over_max_km() {
+        let scorer = DistanceScorer { max_km: 100 };
+        let viewer = viewer(None, None, Vec::new());
+
+        assert_eq!(scorer.score(&viewer, &candidate(None, None, Some(0))), 1.0);
+        assert_eq!(
+            scorer.score(&viewer, &candidate(None, None, Some(25))),
+            0.75
+        );
+        assert_eq!(
+            scorer.score(&viewer, &candidate(None, None, Some(500))),
+            0.0
+        );
+        assert_eq!(scorer.score(&viewer, &candidate(None, None, None)), 0.0);
+    }
+
+    #[test]
+    fn city_score_ignores_case_and_whitespace() {
+        let viewer = viewer(None, Some("Paris"), Vec::new());
+        let mut same_city = candidate(None, None, None);
+        same_city.profile.city = Some(" paris ".to_string());
+        let mut other_city = candidate(None, None, None);
+        other_city.profile.city = Some("Lyon".to_string());
+
+        assert_eq!(CityScorer.score(&viewer, &same_city), 1.0);
+        assert_eq!(CityScorer.score(&viewer, &other_city), 0.0);
+        assert_eq!(CityScorer.score(&viewer, &candidate(None, None, None)), 0.0);
+    }
+
+    #[test]
+    fn gender_score_matches_wanted_genders() {
+        let anyone = viewer(None, None, Vec::new());
+        let picky = viewer(None, None, vec![Gender::Female]);
+
+        assert_eq!(
+            GenderPreferenceScorer.score(&anyone, &candidate(None, Some(Gender::Male), None)),
+            1.0
+        );
+        assert_eq!(
+            GenderPreferenceScorer.score(&picky, &candidate(None, Some(Gender::Female), None)),
+            1.0
+        );
+        assert_eq!(
+            GenderPreferenceScorer.score(&picky, &candidate(None, Some(Gender::Male), None)),
+            0.0
+        );
+        assert_eq!(
+            GenderPreferenceScorer.score(&picky, &candidate(None, None, None)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn shared_interests_score_is_jaccard_index() {
+        let mut viewer = viewer(None, None, Vec::new());
+        viewer.interests = interests(&["hiking", "cooking", "music"]);
+        let mut candidate = candidate(None, None, None);
+        candidate.interests = interests(&["music", "cooking", "chess"]);
+
+        assert_eq!(SharedInterestsScorer.score(&viewer, &candidate), 0.5);
+
+        viewer.interests.clear();
+        candidate.interests.clear();
+        assert_eq!(SharedInterestsScorer.score(&viewer, &candidate), 0.0);
+    }
+
+    #[test]
+    fn weighted_score_is_weighted_average() {
+        let scorer = WeightedScorer::default()
+            .with(3.0, GenderPreferenceScorer)
+            .with(1.0, DistanceScorer { max_km: 100 });
+        let viewer = viewer(None, None, Vec::new());
+
+        // (3.0 * 1.0 + 1.0 * 0.5) / 4.0
+        let score = scorer.score(&viewer, &candidate(None, None, Some(50)));
+        assert_eq!(score, 0.875);
+    }
+
+    #[test]
+    fn empty_weighted_scorer_scores_zero() {
+        let viewer = viewer(None, None, Vec::new());
+        assert_eq!(
+            WeightedScorer::default().score(&viewer, &candidate(None, None, None)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn default_scorer_stays_in_range() {
+        let scorer = default_scorer();
+        let mut viewer = viewer(Some(28), Some("Paris"), vec![Gender::Female]);
+        viewer.interests = interests(&["music"]);
+        let mut best = candidate(Some(28), Some(Gender::Female), Some(0));
+        best.profile.city = Some("Paris".to_string());
+        best.interests = interests(&["music"]);
+        let worst = candidate(None, Some(Gender::Male), None);
+
+        assert_eq!(scorer.score(&viewer, &best), 1.0);
+        assert_eq!(scorer.score(&viewer, &worst), 0.0);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    web::{Data, Path},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthenticatedUser,
+    cache::{Cache, CacheKey},
+    common::{AppError, ResponseToSend},
+};
+
+const MAX_INTERESTS_PER_USER: i64 = 10;
+
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct Interest {
+    pub id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub category: String,
+}
+
+#[derive(FromRow)]
+struct UserInterestRow {
+    user_id: Uuid,
+    #[sqlx(flatten)]
+    interest: Interest,
+}
+
+pub struct Interests;
+
+impl Interests {
+    // Get Interest Catalogue
+    pub async fn get_catalogue(db: Data<PgPool>) -> Result<HttpResponse, AppError> {
+        let interests = sqlx::query_as::<_, Interest>(
+            "SELECT id, slug, name, category FROM interests ORDER BY category, name",
+        )
+        .fetch_all(&**db)
+        .await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Interests Fetch Successfully".to_string(),
+            data: Some(interests),
+        }))
+    }
+
+    // Get My Interests
+    pub async fn get_user_interests(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+    ) -> Result<HttpResponse, AppError> {
+        let interests = Self::for_user(&db, auth_user.user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Interests Fetch Successfully".to_string(),
+            data: Some(interests),
+        }))
+    }
+
+    // Add Interest
+    pub async fn add_interest(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+        let interest_id = path.into_inner();
+
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM interests WHERE id = $1)")
+                .bind(interest_id)
+                .fetch_one(&**db)
+                .await?;
+        if !exists {
+            return Err(AppError::NotFound("Interest Not Found".to_string()));
+        }
+
+        if !Self::insert(&db, user_id, interest_id).await? {
+            return Err(AppError::BadRequest(format!(
+                "You can pick at most {} interests",
+                MAX_INTERESTS_PER_USER
+            )));
+        }
+
+        invalidate(&cache, user_id).await;
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "Interest Added Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    // Add the interest unless the user is already at the cap. Adding one the
+    // user already has is a no-op.
+    async fn insert(db: &PgPool, user_id: Uuid, interest_id: Uuid) -> Result<bool, sqlx::Error> {
+        let mut tx = db.begin().await?;
+
+        // Serialise changes per user so two adds can't both slip under the cap
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+            .bind(format!("interests:{}", user_id))
+            .execute(&mut *tx)
+            .await?;
+
+        let (count, has_interest): (i64, bool) = sqlx::query_as(
+            "SELECT COUNT(*), COALESCE(BOOL_OR(interest_id = $2), FALSE) FROM user_interests WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .bind(interest_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if has_interest {
+            return Ok(true);
+        }
+        if count >= MAX_INTERESTS_PER_USER {
+            return Ok(false);
+        }
+
+        sqlx::query("INSERT INTO user_interests (user_id, interest_id) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(interest_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    // Remove Interest
+    pub async fn remove_interest(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+
+        let deleted =
+            sqlx::query("DELETE FROM user_interests WHERE user_id = $1 AND interest_id = $2")
+                .bind(user_id)
+                .bind(path.into_inner())
+                .execute(&**db)
+                .await?;
+
+        if deleted.rows_affected() == 0 {
+            return Err(AppError::NotFound("Interest Not Found".to_string()));
+        }
+
+        invalidate(&cache, user_id).await;
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "Interest Removed Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    pub async fn for_user(db: &PgPool, user_id: Uuid) -> Result<Vec<Interest>, sqlx::Error> {
+        sqlx::query_as::<_, Interest>(
+            "SELECT i.id, i.slug, i.name, i.category
+             FROM user_interests ui
+             JOIN interests i ON i.id = ui.interest_id
+             WHERE ui.user_id = $1
+             ORDER BY i.category, i.name",
+        )
+        .bind(user_id)
+        .fetch_all(db)
+        .await
+    }
+
+    // Interests of many users in one query, keyed by user id
+    pub async fn for_users(
+        db: &PgPool,
+        user_ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, Vec<Interest>>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, UserInterestRow>(
+            "SELECT ui.user_id, i.id, i.slug, i.name, i.category
+             FROM user_interests ui
+             JOIN interests i ON i.id = ui.interest_id
+             WHERE ui.user_id = ANY($1)
+             ORDER BY i.category, i.name",
+        )
+        .bind(user_ids)
+        .fetch_all(db)
+        .await?;
+
+        let mut interests: HashMap<Uuid, Vec<Interest>> = HashMap::new();
+        for row in rows {
+            interests.entry(row.user_id).or_default().push(row.interest);
+        }
+        Ok(interests)
+    }
+}
+
+// Interests are part of the cached profile and of how the feed is ranked
+async fn invalidate(cache: &Cache, user_id: Uuid) {
+    cache
+        .invalidate(&[CacheKey::UserData(user_id), CacheKey::DiscoverFeed(user_id)])
+        .await;
+}
@@ -0,0 +1,2 @@
+pub mod interests;
+pub use interests::{Interest, Interests};
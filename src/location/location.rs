@@ -0,0 +1,203 @@
+use actix_web::{
+    web::{Data, Json, Query},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthenticatedUser,
+    cache::{Cache, CacheKey},
+    common::{AppError, ResponseToSend},
+    config::Settings,
+    interests::Interests,
+    preferences::{Preferences, MAXIMUM_DISTANCE_KM},
+    user::User,
+};
+
+// Radius used when neither the query nor the user's preferences set one
+const DEFAULT_RADIUS_KM: i32 = 25;
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 50;
+
+#[derive(Deserialize, Debug)]
+pub struct Location {
+    latitude: f64,
+    longitude: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NearbyQuery {
+    radius_km: Option<i32>,
+    page: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct NearbyProfile {
+    user_id: Uuid,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    profile: User,
+    distance_km: Option<i32>,
+}
+
+#[derive(FromRow)]
+struct Coordinates {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+impl Location {
+    // Update Location
+    pub async fn update_location(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        auth_user: AuthenticatedUser,
+        location: Json<Location>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+
+        if !(-90.0..=90.0).contains(&location.latitude) {
+            return Err(AppError::BadRequest(
+                "Latitude must be between -90 and 90".to_string(),
+            ));
+        }
+        if !(-180.0..=180.0).contains(&location.longitude) {
+            return Err(AppError::BadRequest(
+                "Longitude must be between -180 and 180".to_string(),
+            ));
+        }
+
+        // Two decimals is roughly a kilometre, enough to rank by distance
+        // without pinpointing where someone lives
+        let latitude = round_coordinate(location.latitude);
+        let longitude = round_coordinate(location.longitude);
+
+        let updated = sqlx::query(
+            "UPDATE usersdata SET latitude = $1, longitude = $2, location_updated_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE user_id = $3",
+        )
+        .bind(latitude)
+        .bind(longitude)
+        .bind(user_id)
+        .execute(&**db)
+        .await?;
+
+        if updated.rows_affected() == 0 {
+            return Err(AppError::NotFound("User Data Not Found".to_string()));
+        }
+
+        // Distances in the cached feed are now stale
+        cache.invalidate(&[CacheKey::DiscoverFeed(user_id)]).await;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "Location Updated Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    // Get users within a radius, closest first
+    pub async fn nearby(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        settings: Data<Settings>,
+        auth_user: AuthenticatedUser,
+        query: Query<NearbyQuery>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+
+        let coordinates = sqlx::query_as::<_, Coordinates>(
+            "SELECT latitude, longitude FROM usersdata WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&**db)
+        .await?;
+
+        let (latitude, longitude) = match coordinates {
+            Some(Coordinates {
+                latitude: Some(latitude),
+                longitude: Some(longitude),
+            }) => (latitude, longitude),
+            _ => return Err(AppError::BadRequest("Set your location first".to_string())),
+        };
+
+        let radius_km = match query.radius_km {
+            Some(radius_km) => radius_km,
+            None => Preferences::for_user(&db, &cache, &settings.profile, user_id)
+                .await?
+                .max_distance_km
+                .unwrap_or(DEFAULT_RADIUS_KM),
+        };
+        if !(1..=MAXIMUM_DISTANCE_KM).contains(&radius_km) {
+            return Err(AppError::BadRequest(format!(
+                "Radius must be between 1 and {} km",
+                MAXIMUM_DISTANCE_KM
+            )));
+        }
+
+        let page = query.page.unwrap_or(1).max(1);
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let offset = (page - 1)
+            .checked_mul(limit)
+            .ok_or_else(|| AppError::BadRequest("Page Out Of Range".to_string()))?;
+
+        // earth_box narrows the search through the GiST index, earth_distance
+        // trims the corners of the box
+        let mut profiles = sqlx::query_as::<_, NearbyProfile>(
+            "SELECT u.id AS user_id, ud.firstname, ud.lastname, ud.age, ud.gender, ud.bio, ud.city, ud.profile_picture_url,
+                    ROUND(earth_distance(ll_to_earth($2, $3), ll_to_earth(ud.latitude, ud.longitude)) / 1000)::INT AS distance_km
+             FROM users u
+             JOIN usersdata ud ON ud.user_id = u.id
+             WHERE u.id <> $1
+               AND u.email_verified_at IS NOT NULL
+               AND u.status = 'Active' AND (u.suspended_until IS NULL OR u.suspended_until <= CURRENT_TIMESTAMP)
+               AND NOT EXISTS (SELECT 1 FROM blocks b WHERE (b.blocker_id = $1 AND b.blocked_id = u.id) OR (b.blocker_id = u.id AND b.blocked_id = $1))
+               AND earth_box(ll_to_earth($2, $3), $4) @> ll_to_earth(ud.latitude, ud.longitude)
+               AND earth_distance(ll_to_earth($2, $3), ll_to_earth(ud.latitude, ud.longitude)) <= $4
+             ORDER BY earth_distance(ll_to_earth($2, $3), ll_to_earth(ud.latitude, ud.longitude))
+             LIMIT $5 OFFSET $6",
+        )
+        .bind(user_id)
+        .bind(latitude)
+        .bind(longitude)
+        .bind(radius_km as f64 * 1000.0)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&**db)
+        .await?;
+
+        let user_ids: Vec<Uuid> = profiles.iter().map(|p| p.user_id).collect();
+        let mut interests = Interests::for_users(&db, &user_ids).await?;
+        for profile in &mut profiles {
+            profile.profile.interests = interests.remove(&profile.user_id).unwrap_or_default();
+        }
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Nearby Users Fetch Successfully".to_string(),
+            data: Some(profiles),
+        }))
+    }
+}
+
+fn round_coordinate(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coordinates_are_rounded_to_two_decimals() {
+        assert_eq!(round_coordinate(48.856613), 48.86);
+        assert_eq!(round_coordinate(2.352222), 2.35);
+        assert_eq!(round_coordinate(-33.868820), -33.87);
+        assert_eq!(round_coordinate(0.0), 0.0);
+    }
+}
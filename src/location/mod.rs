@@ -0,0 +1,2 @@
+pub mod location;
+pub use location::Location;
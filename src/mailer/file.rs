@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::message::Mailbox;
+use uuid::Uuid;
+
+use super::mailer::{Email, MailError, Mailer};
+
+// Writes every email as an .eml file into a spool directory instead of
+// sending it. Used for local development and tests.
+pub struct FileMailer {
+    spool_dir: PathBuf,
+    from: Mailbox,
+}
+
+impl FileMailer {
+    pub fn new(spool_dir: impl Into<PathBuf>, from: Mailbox) -> Self {
+        FileMailer {
+            spool_dir: spool_dir.into(),
+            from,
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = email.to_message(&self.from)?;
+
+        tokio::fs::create_dir_all(&self.spool_dir).await?;
+        let file_name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            Uuid::new_v4()
+        );
+        let path = self.spool_dir.join(file_name);
+        tokio::fs::write(&path, message.formatted()).await?;
+
+        println!("Email to {} spooled at {}", email.to, path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox, MultiPart, SinglePart},
+    Message,
+};
+
+use super::{file::FileMailer, smtp::SmtpMailer};
+use crate::config::config::{MailSettings, MailTransport};
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    InvalidMessage(String),
+    Transport(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailError::InvalidMessage(e) => write!(f, "invalid email message: {}", e),
+            MailError::Transport(e) => write!(f, "mail transport error: {}", e),
+            MailError::Io(e) => write!(f, "mail spool error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for MailError {
+    fn from(e: std::io::Error) -> Self {
+        MailError::Io(e)
+    }
+}
+
+// Anything able to deliver an email. `send` must only return Ok once the
+// message has been accepted (by the SMTP relay, or written to the spool).
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+impl Email {
+    // Build a multipart/alternative message with both text and HTML bodies
+    pub fn to_message(&self, from: &Mailbox) -> Result<Message, MailError> {
+        let to: Mailbox = self
+            .to
+            .parse()
+            .map_err(|e: lettre::address::AddressError| MailError::InvalidMessage(e.to_string()))?;
+
+        Message::builder()
+            .from(from.clone())
+            .to(to)
+            .subject(&self.subject)
+            .multipart(
+                MultiPart::alternative()
+                    .singlepart(
+                        SinglePart::builder()
+                            .header(ContentType::TEXT_PLAIN)
+                            .body(self.text.clone()),
+                    )
+                    .singlepart(
+                        SinglePart::builder()
+                            .header(ContentType::TEXT_HTML)
+                            .body(self.html.clone()),
+                    ),
+            )
+            .map_err(|e| MailError::InvalidMessage(e.to_string()))
+    }
+}
+
+// Send an email, retrying with exponential backoff when the mailer fails
+pub async fn send_with_retry(
+    mailer: &dyn Mailer,
+    email: &Email,
+    max_attempts: u32,
+) -> Result<(), MailError> {
+    let mut delay = Duration::from_millis(500);
+    let mut attempt = 1;
+
+    loop {
+        match mailer.send(email).await {
+            Ok(_) => return Ok(()),
+            // A malformed message will never succeed, so don't retry it
+            Err(e @ MailError::InvalidMessage(_)) => return Err(e),
+            Err(e) if attempt >= max_attempts => return Err(e),
+            Err(e) => {
+                println!(
+                    "Failed to send email to {} (attempt {}/{}): {}",
+                    email.to, attempt, max_attempts, e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+// Pick the mailer implementation from the configured transport
+pub fn mailer_from_settings(settings: &MailSettings) -> Arc<dyn Mailer> {
+    // Checked when the settings were loaded
+    let from: Mailbox = settings
+        .from
+        .parse()
+        .expect("MAIL_FROM must be a valid mailbox");
+
+    match settings.transport {
+        MailTransport::Smtp => {
+            let host = settings.smtp_host.as_deref().unwrap_or_default();
+            let credentials = settings
+                .smtp_username
+                .clone()
+                .zip(settings.smtp_password.clone());
+
+            Arc::new(
+                SmtpMailer::new(
+                    host,
+                    settings.smtp_port,
+                    settings.smtp_tls,
+                    credentials,
+                    from,
+                )
+                .expect("Failed to create SMTP mailer"),
+            )
+        }
+        MailTransport::File => Arc::new(FileMailer::new(settings.spool_dir.clone(), from)),
+    }
+}
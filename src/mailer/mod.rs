@@ -0,0 +1,5 @@
+pub mod file;
+pub mod mailer;
+pub mod smtp;
+pub mod templates;
+pub use mailer::{mailer_from_settings, send_with_retry, Mailer};
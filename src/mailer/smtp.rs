@@ -0,0 +1,71 @@
+use async_trait::async_trait;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Tokio1Executor,
+};
+use serde::Deserialize;
+use strum_macros::EnumString;
+
+use super::mailer::{Email, MailError, Mailer};
+
+#[derive(Deserialize, EnumString, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    // Implicit TLS, usually on port 465
+    #[serde(rename = "tls")]
+    #[strum(serialize = "tls")]
+    Wrapper,
+    // Upgrade a plain connection with STARTTLS, usually on port 587
+    #[serde(rename = "starttls")]
+    #[strum(serialize = "starttls")]
+    StartTls,
+    // No encryption at all, only meant for local relays such as MailHog
+    #[serde(rename = "none")]
+    #[strum(serialize = "none")]
+    None,
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, String)>,
+        from: Mailbox,
+    ) -> Result<Self, MailError> {
+        let builder = match tls {
+            SmtpTls::Wrapper => AsyncSmtpTransport::<Tokio1Executor>::relay(host),
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host),
+            SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                host,
+            )),
+        }
+        .map_err(|e| MailError::Transport(e.to_string()))?;
+
+        let mut builder = builder.port(port);
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = email.to_message(&self.from)?;
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| MailError::Transport(e.to_string()))
+    }
+}
@@ -0,0 +1,46 @@
+use super::mailer::Email;
+
+const OTP_HTML: &str = include_str!("templates/otp.html");
+const OTP_TEXT: &str = include_str!("templates/otp.txt");
+const PASSWORD_RESET_HTML: &str = include_str!("templates/password_reset.html");
+const PASSWORD_RESET_TEXT: &str = include_str!("templates/password_reset.txt");
+
+// Replace every `{{name}}` placeholder in the template with its value
+fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter()
+        .fold(template.to_string(), |rendered, (name, value)| {
+            rendered.replace(&format!("{{{{{}}}}}", name), value)
+        })
+}
+
+pub fn otp_email(to: &str, username: &str, otp: &str, ttl_seconds: u64) -> Email {
+    let ttl = ttl_seconds.to_string();
+    let vars = [
+        ("username", username),
+        ("otp", otp),
+        ("ttl_seconds", ttl.as_str()),
+    ];
+
+    Email {
+        to: to.to_string(),
+        subject: "Your Amourithm verification code".to_string(),
+        html: render(OTP_HTML, &vars),
+        text: render(OTP_TEXT, &vars),
+    }
+}
+
+pub fn password_reset_email(to: &str, username: &str, token: &str, ttl_seconds: u64) -> Email {
+    let ttl_minutes = (ttl_seconds / 60).max(1).to_string();
+    let vars = [
+        ("username", username),
+        ("token", token),
+        ("ttl_minutes", ttl_minutes.as_str()),
+    ];
+
+    Email {
+        to: to.to_string(),
+        subject: "Reset your Amourithm password".to_string(),
+        html: render(PASSWORD_RESET_HTML, &vars),
+        text: render(PASSWORD_RESET_TEXT, &vars),
+    }
+}
@@ -1,26 +1,98 @@
+// Modules follow the `area/area.rs` layout
+#![allow(clippy::module_inception)]
+
+use actix_files::Files;
 use actix_web::{
-    web::{get, post, Data},
+    middleware::from_fn,
+    web::{delete, get, post, put, scope, Data},
     App, HttpResponse, HttpServer, Responder,
 };
 use std::sync::Arc;
 mod auth;
-use auth::Register;
+mod cache;
+mod chat;
+use auth::{require_auth, require_moderator, Register};
+use chat::{Chat, ChatHub};
 mod connections;
-use connections::*;
+use connections::{migrations, *};
 mod user;
-use ::redis::aio::MultiplexedConnection;
+use ::redis::aio::ConnectionManager;
 use sqlx::{Pool, Postgres};
-use tokio::sync::Mutex;
-use user::User;
+use user::{DataExports, User};
+mod cli;
+use clap::Parser;
+use cli::{Cli, Command};
 mod common;
+mod config;
+use config::Settings;
+mod discovery;
+use discovery::Discovery;
+mod mailer;
+mod matching;
+use matching::Matching;
+mod preferences;
+use preferences::Preferences;
+mod location;
+use location::Location;
+mod interests;
+use interests::Interests;
+mod photos;
+use photos::Photos;
+mod moderation;
+mod storage;
+use moderation::{Moderation, ModerationQueue};
+mod admin;
+use admin::Admin;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let database: Pool<Postgres> = database_connection()
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::Migrate(command)) => {
+            let database_settings = load_or_exit(Settings::load_database());
+            let database = connect_to_database(&database_settings.url).await;
+            if let Err(e) = command.run(&database).await {
+                println!("Migration failed: {}", e);
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Command::Serve) | None => {
+            // Every configuration problem is reported at once, before anything connects
+            let settings = load_or_exit(Settings::load());
+            let database = connect_to_database(&settings.database.url).await;
+            serve(settings, database).await
+        }
+    }
+}
+
+fn load_or_exit<T>(loaded: Result<T, config::config::ConfigError>) -> T {
+    match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn connect_to_database(url: &str) -> Pool<Postgres> {
+    let database = database_connection(url)
         .await
         .expect("Failed to connect to database");
     println!("Database Connection Established");
-    let redis: Arc<Mutex<MultiplexedConnection>> = connect_to_redis()
+    database
+}
+
+async fn serve(settings: Settings, database: Pool<Postgres>) -> std::io::Result<()> {
+    // Serving on an outdated schema breaks requests in confusing ways
+    if let Err(e) = migrations::ensure_schema(&database, settings.database.run_migrations).await {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+
+    let redis: ConnectionManager = connect_to_redis(&settings.redis)
         .await
         .expect("Failed to connect to redis");
     // Share RedisService with the app
@@ -28,21 +100,210 @@ async fn main() -> std::io::Result<()> {
 
     println!("Redis Connection Established");
 
+    let cache = Data::new(cache::Cache::new(
+        redis_service_data.get_ref().clone(),
+        settings.cache.clone(),
+    ));
+
+    let mailer = Data::from(mailer::mailer_from_settings(&settings.mail));
+    let otp_config = Data::new(settings.otp.clone());
+    let session_config = Data::new(settings.session.clone());
+    let scorer: Data<dyn discovery::scorer::Scorer> = Data::from(Arc::new(
+        discovery::scorer::default_scorer(),
+    )
+        as Arc<dyn discovery::scorer::Scorer>);
+    // Signing keys are loaded once, a bad key setup stops the server from starting
+    let keyring = Data::new(
+        auth::keys::Keyring::from_settings(&settings.auth).expect("Failed to load JWT keys"),
+    );
+
+    let blob_store = Data::from(storage::blob_store_from_settings(
+        &settings.storage,
+        &settings.server_url(),
+    ));
+    // Data exports, never served publicly
+    let private_store = Data::new(storage::private_blob_store_from_settings(&settings.storage));
+    // Locally stored uploads are served by the app itself
+    let media_dir = storage::local_media_dir(&settings.storage);
+    if let Some(dir) = &media_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let chat_pubsub = connect_to_redis_pubsub(&settings.redis.url)
+        .await
+        .expect("Failed to open redis pub/sub connection");
+    let chat_hub = Data::new(ChatHub::start(
+        chat_pubsub,
+        settings.redis.url.clone(),
+        redis_service_data.get_ref().clone(),
+    ));
+
+    let photo_processor = Data::new(photos::spawn_photo_processor(
+        database.clone(),
+        cache.get_ref().clone(),
+        blob_store.clone().into_inner(),
+        settings.photos.workers,
+    ));
+
+    let data_exporter = Data::new(user::spawn_data_exporter(
+        database.clone(),
+        blob_store.clone().into_inner(),
+        private_store.get_ref().clone(),
+        settings.accounts.data_export_ttl_hours,
+    ));
+
+    auth::cleanup::spawn_unverified_user_cleanup(database.clone(), &settings.auth);
+    photos::spawn_stale_photo_sweep(database.clone());
+    user::spawn_export_cleanup(database.clone(), private_store.get_ref().clone());
+    user::spawn_account_purge(
+        database.clone(),
+        redis_service_data.get_ref().clone(),
+        cache.get_ref().clone(),
+        blob_store.clone().into_inner(),
+        private_store.get_ref().clone(),
+        &settings.accounts,
+    );
+
+    let bind_address = (settings.server.host.clone(), settings.server.port);
+    let settings = Data::new(settings);
+
     let server = HttpServer::new(move || {
         App::new()
             .app_data(Data::new(database.clone()))
             .app_data(redis_service_data.clone())
+            .app_data(cache.clone())
+            .app_data(settings.clone())
+            .app_data(mailer.clone())
+            .app_data(otp_config.clone())
+            .app_data(session_config.clone())
+            .app_data(keyring.clone())
+            .app_data(scorer.clone())
+            .app_data(blob_store.clone())
+            .app_data(private_store.clone())
+            .app_data(photo_processor.clone())
+            .app_data(data_exporter.clone())
+            .app_data(chat_hub.clone())
+            .configure(|cfg| {
+                if let Some(dir) = &media_dir {
+                    cfg.service(Files::new(storage::MEDIA_ROUTE, dir));
+                }
+            })
             .route("/", get().to(hello_world))
+            .route("/.well-known/jwks.json", get().to(auth::keys::jwks))
             // Auth Routes
             .route("/api/v1/auth/signup", post().to(Register::register_user))
             .route("/api/v1/auth/signin", post().to(Register::login_user))
             .route("/api/v1/auth/verify-otp", post().to(Register::verify_otp))
-            .route("/api/v1/user", get().to(User::get_user))
+            .route("/api/v1/auth/resend-otp", post().to(Register::resend_otp))
+            .route(
+                "/api/v1/auth/forgot-password",
+                post().to(Register::forgot_password),
+            )
+            .route(
+                "/api/v1/auth/reset-password",
+                post().to(Register::reset_password),
+            )
+            .route("/api/v1/auth/refresh", post().to(Register::refresh_token))
+            .route("/api/v1/auth/logout", post().to(Register::logout))
+            .route("/api/v1/auth/logout-all", post().to(Register::logout_all))
             // User Routes
-            .route("/api/v1/user/data", post().to(User::insert_user_data))
-        // .route("/api/v1/user/update", patch().to(User::update_user_details))
+            .service(
+                scope("/api/v1/user")
+                    .wrap(from_fn(require_auth))
+                    .route("", get().to(User::get_user))
+                    .route("", delete().to(User::delete_account))
+                    .route("/export", get().to(DataExports::request_export))
+                    .route(
+                        "/export/{id}/download",
+                        get().to(DataExports::download_export),
+                    )
+                    .route("/data", post().to(User::insert_user_data))
+                    .route("/preferences", get().to(Preferences::get_preferences))
+                    .route("/preferences", put().to(Preferences::update_preferences))
+                    .route("/location", put().to(Location::update_location))
+                    .route("/photos", get().to(Photos::get_photos))
+                    .route("/photos", post().to(Photos::upload_photo))
+                    .route("/photos/order", put().to(Photos::reorder_photos))
+                    .route("/photos/{id}", delete().to(Photos::delete_photo))
+                    .route("/photos/{id}/primary", put().to(Photos::set_primary_photo))
+                    .route("/interests", get().to(Interests::get_user_interests))
+                    .route("/interests/{id}", post().to(Interests::add_interest))
+                    .route("/interests/{id}", delete().to(Interests::remove_interest)),
+                // .route("/update", patch().to(User::update_user_details))
+            )
+            // Interest Catalogue
+            .route("/api/v1/interests", get().to(Interests::get_catalogue))
+            // Nearby Routes, registered before the `{id}` scope so it isn't read as an id
+            .service(
+                scope("/api/v1/users/nearby")
+                    .wrap(from_fn(require_auth))
+                    .route("", get().to(Location::nearby)),
+            )
+            // Swipe, Block and Report Routes
+            .service(
+                scope("/api/v1/users/{id}")
+                    .wrap(from_fn(require_auth))
+                    .route("/like", post().to(Matching::like))
+                    .route("/pass", post().to(Matching::pass))
+                    .route("/super-like", post().to(Matching::super_like))
+                    .route("/block", post().to(Moderation::block_user))
+                    .route("/block", delete().to(Moderation::unblock_user))
+                    .route("/report", post().to(Moderation::report_user)),
+            )
+            // Discovery Routes
+            .service(
+                scope("/api/v1/discover")
+                    .wrap(from_fn(require_auth))
+                    .route("", get().to(Discovery::discover)),
+            )
+            // Match Routes
+            .service(
+                scope("/api/v1/matches")
+                    .wrap(from_fn(require_auth))
+                    .route("", get().to(Matching::get_matches))
+                    .route("/{id}", delete().to(Matching::unmatch)),
+            )
+            // Chat Routes
+            .service(
+                scope("/api/v1/conversations")
+                    .wrap(from_fn(require_auth))
+                    .route("", get().to(Chat::get_conversations))
+                    .route("", post().to(Chat::open_conversation))
+                    .route("/unread-count", get().to(Chat::get_unread_count))
+                    .route("/{id}/messages", get().to(Chat::get_messages))
+                    .route("/{id}/messages", post().to(Chat::post_message))
+                    .route("/{id}/read", post().to(Chat::mark_conversation_read)),
+            )
+            .service(
+                scope("/api/v1/chat")
+                    .wrap(from_fn(require_auth))
+                    .route("/ws", get().to(chat::ws::connect)),
+            )
+            // Admin Routes, for moderators and admins
+            .service(
+                scope("/api/v1/admin")
+                    .wrap(from_fn(require_moderator))
+                    .wrap(from_fn(require_auth))
+                    .route("/users", get().to(Admin::search_users))
+                    .route("/users/{id}", get().to(Admin::get_user))
+                    .route("/users/{id}", delete().to(Admin::delete_user))
+                    .route("/users/{id}/sessions", get().to(Admin::get_sessions))
+                    .route("/users/{id}/suspend", post().to(Admin::suspend_user))
+                    .route("/users/{id}/unsuspend", post().to(Admin::unsuspend_user))
+                    .route("/users/{id}/ban", post().to(Admin::ban_user))
+                    .route("/users/{id}/unban", post().to(Admin::unban_user))
+                    .route("/users/{id}/verify-email", post().to(Admin::verify_email))
+                    .route("/users/{id}/role", put().to(Admin::change_role))
+                    .route("/audit-log", get().to(Admin::get_audit_log))
+                    .route("/reports", get().to(ModerationQueue::get_reports))
+                    .route("/reports/{id}", put().to(ModerationQueue::update_report))
+                    .route(
+                        "/reports/{id}/suspend",
+                        post().to(ModerationQueue::suspend_reported_user),
+                    ),
+            )
     })
-    .bind(("127.0.0.1", 8080))?
+    .bind(bind_address)?
     .run();
 
     server.await
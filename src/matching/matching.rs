@@ -0,0 +1,379 @@
+use actix_web::{
+    web::{Data, Path},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, Postgres, Transaction};
+use strum_macros::Display;
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthenticatedUser,
+    cache::{Cache, CacheKey},
+    chat::{hub::ServerEvent, inbox, unread, ChatHub},
+    common::{AppError, ResponseToSend},
+};
+
+#[derive(sqlx::Type, Debug, Deserialize, Display, Serialize, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum SwipeKind {
+    Like,
+    Pass,
+    SuperLike,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SwipeResult {
+    matched: bool,
+    match_id: Option<Uuid>,
+}
+
+// Outcome of a swipe, with the events to push once it is committed
+struct RecordedSwipe {
+    match_id: Option<Uuid>,
+    events: Vec<(Uuid, ServerEvent)>,
+}
+
+// A match as seen by one of its two users
+#[derive(Serialize, FromRow, Debug)]
+pub struct Match {
+    id: Uuid,
+    user_id: Uuid,
+    firstname: Option<String>,
+    age: Option<i32>,
+    city: Option<String>,
+    profile_picture_url: Option<String>,
+    distance_km: Option<i32>,
+    matched_at: Option<DateTime<Utc>>,
+}
+
+pub struct Matching;
+
+impl Matching {
+    pub async fn like(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        hub: Data<ChatHub>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        Self::swipe(
+            db,
+            cache,
+            hub,
+            auth_user.user_id,
+            path.into_inner(),
+            SwipeKind::Like,
+        )
+        .await
+    }
+
+    pub async fn pass(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        hub: Data<ChatHub>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        Self::swipe(
+            db,
+            cache,
+            hub,
+            auth_user.user_id,
+            path.into_inner(),
+            SwipeKind::Pass,
+        )
+        .await
+    }
+
+    pub async fn super_like(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        hub: Data<ChatHub>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        Self::swipe(
+            db,
+            cache,
+            hub,
+            auth_user.user_id,
+            path.into_inner(),
+            SwipeKind::SuperLike,
+        )
+        .await
+    }
+
+    // Record a swipe and create the match when a like is reciprocated
+    async fn swipe(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        hub: Data<ChatHub>,
+        user_id: Uuid,
+        target_id: Uuid,
+        kind: SwipeKind,
+    ) -> Result<HttpResponse, AppError> {
+        if user_id == target_id {
+            return Err(AppError::BadRequest(
+                "You cannot swipe on yourself".to_string(),
+            ));
+        }
+
+        let target_exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND email_verified_at IS NOT NULL
+                    AND status = 'Active' AND (suspended_until IS NULL OR suspended_until <= CURRENT_TIMESTAMP))
+                AND NOT EXISTS(SELECT 1 FROM blocks WHERE (blocker_id = $1 AND blocked_id = $2) OR (blocker_id = $2 AND blocked_id = $1))",
+        )
+        .bind(target_id)
+        .bind(user_id)
+        .fetch_one(&**db)
+        .await?;
+
+        if !target_exists {
+            return Err(AppError::NotFound("User Not Found".to_string()));
+        }
+
+        let mut tx = db.begin().await?;
+        let recorded = Self::record_swipe(&mut tx, user_id, target_id, kind).await?;
+        let match_id = recorded.match_id;
+        tx.commit().await?;
+
+        // The swiped profile must not come back in the feed
+        cache.invalidate(&[CacheKey::DiscoverFeed(user_id)]).await;
+
+        // Users who aren't connected get these from their inbox later
+        for (recipient_id, event) in &recorded.events {
+            hub.publish(*recipient_id, event).await;
+        }
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: match match_id {
+                Some(_) => "It's a Match".to_string(),
+                None => format!("{} Recorded Successfully", kind),
+            },
+            data: Some(SwipeResult {
+                matched: match_id.is_some(),
+                match_id,
+            }),
+        }))
+    }
+
+    async fn record_swipe(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        target_id: Uuid,
+        kind: SwipeKind,
+    ) -> Result<RecordedSwipe, sqlx::Error> {
+        let (user_one_id, user_two_id) = ordered_pair(user_id, target_id);
+
+        // Serialise swipes between the same two users so that two likes
+        // sent at the same moment can't both miss each other
+        lock_pair(tx, user_id, target_id).await?;
+
+        sqlx::query(
+            "INSERT INTO likes (id, liker_id, likee_id, kind) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (liker_id, likee_id) DO UPDATE SET kind = EXCLUDED.kind, created_at = CURRENT_TIMESTAMP",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(target_id)
+        .bind(kind)
+        .execute(&mut **tx)
+        .await?;
+
+        let mut recorded = RecordedSwipe {
+            match_id: None,
+            events: Vec::new(),
+        };
+
+        if kind == SwipeKind::Pass {
+            return Ok(recorded);
+        }
+
+        // A block made after the check in `swipe` is caught here, blocking
+        // takes the same lock
+        let is_reciprocated: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM likes WHERE liker_id = $1 AND likee_id = $2 AND kind IN ('Like', 'SuperLike'))
+                AND NOT EXISTS(SELECT 1 FROM blocks WHERE (blocker_id = $1 AND blocked_id = $2) OR (blocker_id = $2 AND blocked_id = $1))",
+        )
+        .bind(target_id)
+        .bind(user_id)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        if !is_reciprocated {
+            return Ok(recorded);
+        }
+
+        let created: Option<(Uuid, DateTime<Utc>)> = sqlx::query_as(
+            "INSERT INTO matches (id, user_one_id, user_two_id) VALUES ($1, $2, $3) ON CONFLICT (user_one_id, user_two_id) DO NOTHING
+             RETURNING id, created_at",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_one_id)
+        .bind(user_two_id)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        // Only a new match is announced, liking an existing match again isn't
+        if let Some((match_id, matched_at)) = created {
+            for (recipient_id, other_user_id) in [(user_id, target_id), (target_id, user_id)] {
+                let event_id = Uuid::new_v4();
+                let event = ServerEvent::Match {
+                    event_id,
+                    match_id,
+                    user_id: other_user_id,
+                    matched_at,
+                };
+                inbox::store(tx, recipient_id, event_id, &event).await?;
+                recorded.events.push((recipient_id, event));
+            }
+        }
+
+        recorded.match_id = sqlx::query_scalar(
+            "SELECT id FROM matches WHERE user_one_id = $1 AND user_two_id = $2",
+        )
+        .bind(user_one_id)
+        .bind(user_two_id)
+        .fetch_optional(&mut **tx)
+        .await?;
+        Ok(recorded)
+    }
+
+    // Get Matches
+    pub async fn get_matches(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+    ) -> Result<HttpResponse, AppError> {
+        let matches = sqlx::query_as::<_, Match>(
+            "SELECT m.id, other.id AS user_id, ud.firstname, ud.age, ud.city, ud.profile_picture_url,
+                    ROUND(earth_distance(ll_to_earth(me.latitude, me.longitude), ll_to_earth(ud.latitude, ud.longitude)) / 1000)::INT AS distance_km,
+                    m.created_at AS matched_at
+             FROM matches m
+             JOIN users other ON other.id = CASE WHEN m.user_one_id = $1 THEN m.user_two_id ELSE m.user_one_id END
+             LEFT JOIN usersdata ud ON ud.user_id = other.id
+             LEFT JOIN usersdata me ON me.user_id = $1
+             WHERE m.user_one_id = $1 OR m.user_two_id = $1
+             ORDER BY m.created_at DESC",
+        )
+        .bind(auth_user.user_id)
+        .fetch_all(&**db)
+        .await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Matches Fetch Successfully".to_string(),
+            data: Some(matches),
+        }))
+    }
+
+    // Unmatch
+    pub async fn unmatch(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let match_id = path.into_inner();
+        let user_id = auth_user.user_id;
+
+        let mut tx = db.begin().await?;
+
+        let other_user_id: Option<Uuid> = sqlx::query_scalar(
+            "DELETE FROM matches WHERE id = $1 AND (user_one_id = $2 OR user_two_id = $2)
+             RETURNING CASE WHEN user_one_id = $2 THEN user_two_id ELSE user_one_id END",
+        )
+        .bind(match_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(other_user_id) = other_user_id else {
+            return Err(AppError::NotFound("Match Not Found".to_string()));
+        };
+
+        Self::release_match(&mut tx, match_id, user_id, other_user_id).await?;
+        tx.commit().await?;
+
+        // The conversation went with the match
+        unread::invalidate(&redis, &[user_id, other_user_id]).await;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "Unmatched Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    // Remove the match between two users if there is one, `user_id` being
+    // the one ending it
+    pub async fn end_match(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        other_user_id: Uuid,
+    ) -> Result<Option<Uuid>, sqlx::Error> {
+        let (user_one_id, user_two_id) = ordered_pair(user_id, other_user_id);
+
+        let match_id: Option<Uuid> = sqlx::query_scalar(
+            "DELETE FROM matches WHERE user_one_id = $1 AND user_two_id = $2 RETURNING id",
+        )
+        .bind(user_one_id)
+        .bind(user_two_id)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        if let Some(match_id) = match_id {
+            Self::release_match(tx, match_id, user_id, other_user_id).await?;
+        }
+        Ok(match_id)
+    }
+
+    // Clean up after a deleted match
+    async fn release_match(
+        tx: &mut Transaction<'_, Postgres>,
+        match_id: Uuid,
+        user_id: Uuid,
+        other_user_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        // Turn the like into a pass so the pair doesn't match again right away
+        sqlx::query("UPDATE likes SET kind = $1 WHERE liker_id = $2 AND likee_id = $3")
+            .bind(SwipeKind::Pass)
+            .bind(user_id)
+            .bind(other_user_id)
+            .execute(&mut **tx)
+            .await?;
+
+        // Don't announce a match that no longer exists
+        sqlx::query("DELETE FROM inbox_events WHERE payload->>'match_id' = $1")
+            .bind(match_id.to_string())
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+}
+
+// Matches store their two users in a fixed order
+fn ordered_pair(a: Uuid, b: Uuid) -> (Uuid, Uuid) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// Serialise changes to the relationship between two users
+pub async fn lock_pair(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    other_user_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let (user_one_id, user_two_id) = ordered_pair(user_id, other_user_id);
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+        .bind(format!("{}:{}", user_one_id, user_two_id))
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,4 @@
+pub mod moderation;
+pub mod queue;
+pub use moderation::Moderation;
+pub use queue::ModerationQueue;
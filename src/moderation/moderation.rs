@@ -0,0 +1,237 @@
+use actix_web::{
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use strum_macros::Display;
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthenticatedUser,
+    cache::{Cache, CacheKey},
+    chat::unread,
+    common::{AppError, ResponseToSend},
+    matching::{matching::lock_pair, Matching},
+};
+
+pub const MAX_REPORT_DETAILS_LENGTH: usize = 1000;
+pub const MAX_SUSPENSION_DAYS: i64 = 365;
+
+#[derive(sqlx::Type, Debug, Deserialize, Display, Serialize, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum ReportReason {
+    Spam,
+    FakeProfile,
+    Harassment,
+    InappropriateContent,
+    Underage,
+    Scam,
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Report {
+    reason: ReportReason,
+    details: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Suspension {
+    pub days: i64,
+    pub reason: String,
+}
+
+impl Suspension {
+    // The trimmed reason, or why the suspension can't be applied
+    pub fn validate(&self) -> Result<&str, String> {
+        if !(1..=MAX_SUSPENSION_DAYS).contains(&self.days) {
+            return Err(format!(
+                "Suspension must last between 1 and {} days",
+                MAX_SUSPENSION_DAYS
+            ));
+        }
+        let reason = self.reason.trim();
+        if reason.is_empty() {
+            return Err("Reason is required".to_string());
+        }
+        Ok(reason)
+    }
+}
+
+pub struct Moderation;
+
+impl Moderation {
+    // Block User, hiding the two users from each other
+    pub async fn block_user(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        cache: Data<Cache>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+        let blocked_id = path.into_inner();
+
+        if user_id == blocked_id {
+            return Err(AppError::BadRequest(
+                "You cannot block yourself".to_string(),
+            ));
+        }
+        if !user_exists(&db, blocked_id).await? {
+            return Err(AppError::NotFound("User Not Found".to_string()));
+        }
+
+        Self::block(&db, user_id, blocked_id).await?;
+
+        // Both cached feeds may still list the other user, and the
+        // conversation went with the match
+        cache
+            .invalidate(&[
+                CacheKey::DiscoverFeed(user_id),
+                CacheKey::DiscoverFeed(blocked_id),
+            ])
+            .await;
+        unread::invalidate(&redis, &[user_id, blocked_id]).await;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "User Blocked Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    async fn block(db: &PgPool, user_id: Uuid, blocked_id: Uuid) -> Result<(), sqlx::Error> {
+        let mut tx = db.begin().await?;
+
+        // Same lock as swipes, so a like in flight can't recreate the match
+        lock_pair(&mut tx, user_id, blocked_id).await?;
+
+        sqlx::query(
+            "INSERT INTO blocks (blocker_id, blocked_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(user_id)
+        .bind(blocked_id)
+        .execute(&mut *tx)
+        .await?;
+
+        // Removes the conversation too
+        Matching::end_match(&mut tx, user_id, blocked_id).await?;
+
+        tx.commit().await
+    }
+
+    // Unblock User. A match that ended with the block isn't restored.
+    pub async fn unblock_user(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+
+        let deleted = sqlx::query("DELETE FROM blocks WHERE blocker_id = $1 AND blocked_id = $2")
+            .bind(user_id)
+            .bind(path.into_inner())
+            .execute(&**db)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(AppError::NotFound("Block Not Found".to_string()));
+        }
+
+        cache.invalidate(&[CacheKey::DiscoverFeed(user_id)]).await;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "User Unblocked Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    // Report User to the moderation queue
+    pub async fn report_user(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+        report: Json<Report>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+        let reported_id = path.into_inner();
+
+        if user_id == reported_id {
+            return Err(AppError::BadRequest(
+                "You cannot report yourself".to_string(),
+            ));
+        }
+
+        let details = report
+            .details
+            .as_deref()
+            .map(str::trim)
+            .filter(|details| !details.is_empty());
+        if details.is_some_and(|details| details.chars().count() > MAX_REPORT_DETAILS_LENGTH) {
+            return Err(AppError::BadRequest(format!(
+                "Details must be at most {} characters",
+                MAX_REPORT_DETAILS_LENGTH
+            )));
+        }
+        if report.reason == ReportReason::Other && details.is_none() {
+            return Err(AppError::BadRequest("Tell us what happened".to_string()));
+        }
+
+        if !user_exists(&db, reported_id).await? {
+            return Err(AppError::NotFound("User Not Found".to_string()));
+        }
+
+        // Reporting the same user again while a report is pending is a no-op
+        sqlx::query(
+            "INSERT INTO reports (id, reporter_id, reported_id, reason, details) VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (reporter_id, reported_id) WHERE status IN ('Open', 'Triaged') DO NOTHING",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(reported_id)
+        .bind(report.reason)
+        .bind(details)
+        .execute(&**db)
+        .await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "User Reported Successfully".to_string(),
+            data: None,
+        }))
+    }
+}
+
+async fn user_exists(db: &PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)")
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+}
+
+// Suspend the user, keeping a longer suspension already in place. Returns
+// false when the user doesn't exist. Their sessions should be revoked once
+// this is committed.
+pub async fn suspend_user(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    days: i64,
+    reason: &str,
+) -> Result<bool, sqlx::Error> {
+    let updated = sqlx::query(
+        "UPDATE users
+         SET suspended_until = GREATEST(suspended_until, CURRENT_TIMESTAMP + make_interval(days => $2)),
+             suspension_reason = $3, updated_at = CURRENT_TIMESTAMP
+         WHERE id = $1",
+    )
+    .bind(user_id)
+    .bind(days as i32)
+    .bind(reason)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(updated.rows_affected() > 0)
+}
@@ -0,0 +1,326 @@
+use actix_web::{
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{prelude::FromRow, PgPool};
+use strum_macros::Display;
+use uuid::Uuid;
+
+use super::moderation::{suspend_user, ReportReason, Suspension};
+use crate::{
+    admin::audit::{self, AuditAction},
+    auth::{session::revoke_all_sessions, AuthenticatedUser, Role},
+    common::{AppError, ResponseToSend},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(sqlx::Type, Debug, Deserialize, Display, Serialize, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum ReportStatus {
+    Open,
+    Triaged,
+    Resolved,
+    Dismissed,
+}
+
+impl ReportStatus {
+    // Statuses a report can move to this one from
+    fn previous(self) -> &'static [ReportStatus] {
+        match self {
+            ReportStatus::Open => &[],
+            ReportStatus::Triaged => &[ReportStatus::Open],
+            ReportStatus::Resolved | ReportStatus::Dismissed => {
+                &[ReportStatus::Open, ReportStatus::Triaged]
+            }
+        }
+    }
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct ReportEntry {
+    id: Uuid,
+    reporter_id: Uuid,
+    reported_id: Uuid,
+    reported_username: String,
+    reason: ReportReason,
+    details: Option<String>,
+    status: ReportStatus,
+    moderator_note: Option<String>,
+    // Reports ever filed against the reported user
+    reports_against: i64,
+    suspended_until: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    resolved_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReportQuery {
+    // Open and Triaged reports when not set
+    status: Option<ReportStatus>,
+    page: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReportUpdate {
+    status: ReportStatus,
+    note: Option<String>,
+}
+
+// Outcome of suspending a reported user
+enum Suspended {
+    User(Uuid),
+    ReportNotFound,
+    // The reported user has a role the moderator can't act on
+    Forbidden,
+}
+
+pub struct ModerationQueue;
+
+impl ModerationQueue {
+    // Get Reports, oldest first
+    pub async fn get_reports(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        query: Query<ReportQuery>,
+    ) -> Result<HttpResponse, AppError> {
+        let statuses: Vec<String> = match query.status {
+            Some(status) => vec![status.to_string()],
+            None => vec![
+                ReportStatus::Open.to_string(),
+                ReportStatus::Triaged.to_string(),
+            ],
+        };
+        let page = query.page.unwrap_or(1).max(1);
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let offset = (page - 1)
+            .checked_mul(limit)
+            .ok_or_else(|| AppError::BadRequest("Page Out Of Range".to_string()))?;
+
+        let reports = sqlx::query_as::<_, ReportEntry>(
+            "SELECT r.id, r.reporter_id, r.reported_id, u.username AS reported_username, r.reason, r.details, r.status,
+                    r.moderator_note, (SELECT COUNT(*) FROM reports o WHERE o.reported_id = r.reported_id) AS reports_against,
+                    u.suspended_until, r.created_at, r.updated_at, r.resolved_at
+             FROM reports r
+             JOIN users u ON u.id = r.reported_id
+             WHERE r.status = ANY($1)
+             ORDER BY r.created_at, r.id
+             LIMIT $2 OFFSET $3",
+        )
+        .bind(&statuses)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&**db)
+        .await?;
+
+        let details = json!({ "status": query.status, "page": page });
+        audit::record(
+            &**db,
+            auth_user.user_id,
+            AuditAction::ViewReports,
+            None,
+            details,
+        )
+        .await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Reports Fetch Successfully".to_string(),
+            data: Some(reports),
+        }))
+    }
+
+    // Update Report, to triage, resolve or dismiss it
+    pub async fn update_report(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+        update: Json<ReportUpdate>,
+    ) -> Result<HttpResponse, AppError> {
+        let report_id = path.into_inner();
+        if update.status == ReportStatus::Open {
+            return Err(AppError::BadRequest(
+                "Reports can't be reopened".to_string(),
+            ));
+        }
+        let previous: Vec<String> = update
+            .status
+            .previous()
+            .iter()
+            .map(|status| status.to_string())
+            .collect();
+
+        let updated = Self::set_status(
+            &db,
+            auth_user.user_id,
+            report_id,
+            update.status,
+            update.note.as_deref().map(str::trim),
+            &previous,
+        )
+        .await?;
+        if !updated {
+            return Err(Self::report_not_updated(&db, report_id).await);
+        }
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: format!("Report {} Successfully", update.status),
+            data: None,
+        }))
+    }
+
+    async fn set_status(
+        db: &PgPool,
+        actor_id: Uuid,
+        report_id: Uuid,
+        status: ReportStatus,
+        note: Option<&str>,
+        previous: &[String],
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = db.begin().await?;
+
+        let reported_id: Option<Uuid> = sqlx::query_scalar(
+            "UPDATE reports
+             SET status = $1, moderator_note = COALESCE($2, moderator_note), updated_at = CURRENT_TIMESTAMP,
+                 resolved_at = CASE WHEN $1 IN ('Resolved', 'Dismissed') THEN CURRENT_TIMESTAMP END
+             WHERE id = $3 AND status = ANY($4)
+             RETURNING reported_id",
+        )
+        .bind(status)
+        .bind(note)
+        .bind(report_id)
+        .bind(previous)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(reported_id) = reported_id else {
+            return Ok(false);
+        };
+
+        let details = json!({ "report_id": report_id, "status": status, "note": note });
+        audit::record(
+            &mut *tx,
+            actor_id,
+            AuditAction::UpdateReport,
+            Some(reported_id),
+            details,
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    // Suspend the reported user and resolve the report
+    pub async fn suspend_reported_user(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+        suspension: Json<Suspension>,
+    ) -> Result<HttpResponse, AppError> {
+        let report_id = path.into_inner();
+
+        let reason = suspension.validate().map_err(AppError::BadRequest)?;
+
+        let suspended = Self::suspend(&db, &auth_user, report_id, suspension.days, reason).await?;
+        let reported_id = match suspended {
+            Suspended::User(reported_id) => reported_id,
+            Suspended::ReportNotFound => {
+                return Err(AppError::NotFound("Report Not Found".to_string()))
+            }
+            Suspended::Forbidden => {
+                return Err(AppError::Forbidden(
+                    "You can't suspend this user".to_string(),
+                ))
+            }
+        };
+
+        // Signs the user out everywhere
+        let mut redis_conn = redis.get_ref().clone();
+        revoke_all_sessions(&mut redis_conn, reported_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "User Suspended Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    async fn suspend(
+        db: &PgPool,
+        actor: &AuthenticatedUser,
+        report_id: Uuid,
+        days: i64,
+        reason: &str,
+    ) -> Result<Suspended, sqlx::Error> {
+        let mut tx = db.begin().await?;
+
+        let reported: Option<(Uuid, Role)> = sqlx::query_as(
+            "SELECT u.id, u.role FROM reports r JOIN users u ON u.id = r.reported_id WHERE r.id = $1",
+        )
+        .bind(report_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some((reported_id, reported_role)) = reported else {
+            return Ok(Suspended::ReportNotFound);
+        };
+        if actor.user_id == reported_id || !actor.claims.role.outranks(reported_role) {
+            return Ok(Suspended::Forbidden);
+        }
+
+        sqlx::query(
+            "UPDATE reports
+             SET status = CASE WHEN status IN ('Open', 'Triaged') THEN 'Resolved' ELSE status END,
+                 moderator_note = COALESCE(moderator_note, $2), updated_at = CURRENT_TIMESTAMP,
+                 resolved_at = COALESCE(resolved_at, CURRENT_TIMESTAMP)
+             WHERE id = $1",
+        )
+        .bind(report_id)
+        .bind(reason)
+        .execute(&mut *tx)
+        .await?;
+
+        suspend_user(&mut tx, reported_id, days, reason).await?;
+
+        let details = json!({ "report_id": report_id, "days": days, "reason": reason });
+        audit::record(
+            &mut *tx,
+            actor.user_id,
+            AuditAction::SuspendUser,
+            Some(reported_id),
+            details,
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(Suspended::User(reported_id))
+    }
+
+    // Why a report couldn't be moved to the requested status
+    async fn report_not_updated(db: &PgPool, report_id: Uuid) -> AppError {
+        let status: Result<Option<ReportStatus>, sqlx::Error> =
+            sqlx::query_scalar("SELECT status FROM reports WHERE id = $1")
+                .bind(report_id)
+                .fetch_optional(db)
+                .await;
+
+        match status {
+            Ok(Some(status)) => AppError::Conflict(format!("Report is already {}", status)),
+            Ok(None) => AppError::NotFound("Report Not Found".to_string()),
+            Err(e) => e.into(),
+        }
+    }
+}
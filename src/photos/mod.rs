@@ -0,0 +1,4 @@
+pub mod photos;
+pub mod processing;
+pub use photos::{Photo, Photos};
+pub use processing::{spawn_photo_processor, spawn_stale_photo_sweep};
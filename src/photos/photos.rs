@@ -0,0 +1,468 @@
+use actix_multipart::Multipart;
+use actix_web::{
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, Postgres, Transaction};
+use strum_macros::Display;
+use uuid::Uuid;
+
+use super::processing::{variant_key, PhotoProcessor, ProcessingJob, FULL, MEDIUM, THUMBNAIL};
+use crate::{
+    auth::AuthenticatedUser,
+    cache::{Cache, CacheKey},
+    common::{AppError, ResponseToSend},
+    storage::BlobStore,
+};
+
+const MAX_PHOTOS_PER_USER: i64 = 6;
+const MAX_PHOTO_BYTES: usize = 10 * 1024 * 1024;
+// Name of the multipart field carrying the file
+const PHOTO_FIELD: &str = "photo";
+
+#[derive(sqlx::Type, Debug, Deserialize, Display, Serialize, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum PhotoStatus {
+    Processing,
+    Ready,
+    Failed,
+}
+
+// URLs are only set once the photo is Ready
+#[derive(Serialize, FromRow, Debug)]
+pub struct Photo {
+    id: Uuid,
+    status: PhotoStatus,
+    url: Option<String>,
+    medium_url: Option<String>,
+    thumbnail_url: Option<String>,
+    position: i32,
+    is_primary: bool,
+    created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PhotoOrder {
+    photo_ids: Vec<Uuid>,
+}
+
+pub struct Photos;
+
+impl Photos {
+    // Get Photos
+    pub async fn get_photos(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+    ) -> Result<HttpResponse, AppError> {
+        let photos = Self::list(&db, auth_user.user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Photos Fetch Successfully".to_string(),
+            data: Some(photos),
+        }))
+    }
+
+    // Upload Photo, sent as multipart/form-data in the `photo` field. The
+    // photo is processed in the background and starts out as Processing.
+    pub async fn upload_photo(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        processor: Data<PhotoProcessor>,
+        auth_user: AuthenticatedUser,
+        payload: Multipart,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+
+        // Cheap check before reading the upload, repeated under the lock below
+        if Self::count(&db, user_id).await? >= MAX_PHOTOS_PER_USER {
+            return Err(too_many_photos());
+        }
+
+        let (bytes, image_type) = read_photo(payload).await?;
+
+        let photo_id = Uuid::new_v4();
+        let Some(photo) = Self::insert(&db, user_id, photo_id, image_type).await? else {
+            return Err(too_many_photos());
+        };
+
+        let job = ProcessingJob {
+            photo_id,
+            user_id,
+            bytes,
+        };
+        if processor.enqueue(job).is_err() {
+            // Nothing will ever process this row, so don't leave it behind
+            if let Err(e) = Self::remove(&db, user_id, photo_id).await {
+                println!("Failed to remove unprocessed photo {}: {}", photo_id, e);
+            }
+            return Err(AppError::ServiceUnavailable(
+                "Too many photos are being processed, try again shortly".to_string(),
+            ));
+        }
+
+        invalidate_user_data(&cache, user_id).await;
+        Ok(HttpResponse::Accepted().json(ResponseToSend {
+            success: true,
+            message: "Photo Uploaded Successfully".to_string(),
+            data: Some(photo),
+        }))
+    }
+
+    // Insert the photo unless the user already has the maximum. The first
+    // photo becomes the primary one. Variant keys are decided up front so a
+    // delete can clean them up whether or not processing has finished.
+    async fn insert(
+        db: &PgPool,
+        user_id: Uuid,
+        photo_id: Uuid,
+        image_type: ImageType,
+    ) -> Result<Option<Photo>, sqlx::Error> {
+        let mut tx = db.begin().await?;
+        lock_photos(&mut tx, user_id).await?;
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM photos WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if count >= MAX_PHOTOS_PER_USER {
+            return Ok(None);
+        }
+
+        let photo = sqlx::query_as::<_, Photo>(
+            "INSERT INTO photos (id, user_id, storage_key, medium_key, thumbnail_key, content_type, position, is_primary, status)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+             RETURNING id, status, url, medium_url, thumbnail_url, position, is_primary, created_at",
+        )
+        .bind(photo_id)
+        .bind(user_id)
+        .bind(variant_key(user_id, photo_id, &FULL))
+        .bind(variant_key(user_id, photo_id, &MEDIUM))
+        .bind(variant_key(user_id, photo_id, &THUMBNAIL))
+        .bind(image_type.content_type())
+        .bind(count as i32)
+        .bind(count == 0)
+        .bind(PhotoStatus::Processing)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sync_profile_picture(&mut tx, user_id).await?;
+        tx.commit().await?;
+        Ok(Some(photo))
+    }
+
+    // Delete Photo
+    pub async fn delete_photo(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        blob_store: Data<dyn BlobStore>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+
+        let Some(storage_keys) = Self::remove(&db, user_id, path.into_inner()).await? else {
+            return Err(AppError::NotFound("Photo Not Found".to_string()));
+        };
+        invalidate_user_data(&cache, user_id).await;
+
+        for storage_key in storage_keys {
+            if let Err(e) = blob_store.delete(&storage_key).await {
+                println!("Failed to delete photo {}: {}", storage_key, e);
+            }
+        }
+
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "Photo Deleted Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    // Remove the photo row and close the gap it leaves, returning its keys
+    async fn remove(
+        db: &PgPool,
+        user_id: Uuid,
+        photo_id: Uuid,
+    ) -> Result<Option<Vec<String>>, sqlx::Error> {
+        let mut tx = db.begin().await?;
+        lock_photos(&mut tx, user_id).await?;
+
+        let deleted: Option<(String, Option<String>, Option<String>, bool)> = sqlx::query_as(
+            "DELETE FROM photos WHERE id = $1 AND user_id = $2 RETURNING storage_key, medium_key, thumbnail_key, is_primary",
+        )
+        .bind(photo_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some((storage_key, medium_key, thumbnail_key, was_primary)) = deleted else {
+            return Ok(None);
+        };
+
+        sqlx::query(
+            "UPDATE photos p SET position = ordered.position
+             FROM (SELECT id, (ROW_NUMBER() OVER (ORDER BY position) - 1)::INT AS position FROM photos WHERE user_id = $1) ordered
+             WHERE p.id = ordered.id",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+        // Hand the primary flag to the first remaining photo
+        if was_primary {
+            sqlx::query("UPDATE photos SET is_primary = TRUE WHERE user_id = $1 AND position = 0")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sync_profile_picture(&mut tx, user_id).await?;
+        tx.commit().await?;
+        Ok(Some(
+            [Some(storage_key), medium_key, thumbnail_key]
+                .into_iter()
+                .flatten()
+                .collect(),
+        ))
+    }
+
+    // Reorder Photos, `photo_ids` must list every photo of the user once
+    pub async fn reorder_photos(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        order: Json<PhotoOrder>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+
+        let mut tx = db.begin().await?;
+        lock_photos(&mut tx, user_id).await?;
+
+        let mut current: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM photos WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        let mut requested = order.photo_ids.clone();
+        current.sort();
+        requested.sort();
+        if current != requested {
+            return Err(AppError::BadRequest(
+                "photo_ids must list each of your photos exactly once".to_string(),
+            ));
+        }
+
+        sqlx::query(
+            "UPDATE photos p SET position = (o.ordinality - 1)::INT
+             FROM unnest($2::UUID[]) WITH ORDINALITY AS o(id, ordinality)
+             WHERE p.id = o.id AND p.user_id = $1",
+        )
+        .bind(user_id)
+        .bind(&order.photo_ids)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let photos = Self::list(&db, user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Photos Reordered Successfully".to_string(),
+            data: Some(photos),
+        }))
+    }
+
+    // Set Primary Photo
+    pub async fn set_primary_photo(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+
+        if !Self::make_primary(&db, user_id, path.into_inner()).await? {
+            return Err(AppError::NotFound("Photo Not Found".to_string()));
+        }
+
+        invalidate_user_data(&cache, user_id).await;
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "Primary Photo Updated Successfully".to_string(),
+            data: None,
+        }))
+    }
+
+    async fn make_primary(db: &PgPool, user_id: Uuid, photo_id: Uuid) -> Result<bool, sqlx::Error> {
+        let mut tx = db.begin().await?;
+        lock_photos(&mut tx, user_id).await?;
+
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM photos WHERE id = $1 AND user_id = $2)",
+        )
+        .bind(photo_id)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !exists {
+            return Ok(false);
+        }
+
+        // Two steps, the partial unique index is checked row by row
+        sqlx::query("UPDATE photos SET is_primary = FALSE WHERE user_id = $1 AND is_primary")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE photos SET is_primary = TRUE WHERE id = $1")
+            .bind(photo_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sync_profile_picture(&mut tx, user_id).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    pub async fn list(db: &PgPool, user_id: Uuid) -> Result<Vec<Photo>, sqlx::Error> {
+        sqlx::query_as::<_, Photo>(
+            "SELECT id, status, url, medium_url, thumbnail_url, position, is_primary, created_at FROM photos WHERE user_id = $1 ORDER BY position",
+        )
+        .bind(user_id)
+        .fetch_all(db)
+        .await
+    }
+
+    // Blob store keys of every variant of the user's photos
+    pub async fn storage_keys(db: &PgPool, user_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT key FROM photos, unnest(ARRAY[storage_key, medium_key, thumbnail_key]) AS key
+             WHERE user_id = $1 AND key IS NOT NULL",
+        )
+        .bind(user_id)
+        .fetch_all(db)
+        .await
+    }
+
+    async fn count(db: &PgPool, user_id: Uuid) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM photos WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(db)
+            .await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageType {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl ImageType {
+    // Identify the image from its first bytes rather than trusting the client
+    fn sniff(bytes: &[u8]) -> Option<ImageType> {
+        if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageType::Jpeg)
+        } else if bytes.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+            Some(ImageType::Png)
+        } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(ImageType::Webp)
+        } else {
+            None
+        }
+    }
+
+    fn from_content_type(content_type: &str) -> Option<ImageType> {
+        match content_type {
+            "image/jpeg" => Some(ImageType::Jpeg),
+            "image/png" => Some(ImageType::Png),
+            "image/webp" => Some(ImageType::Webp),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ImageType::Jpeg => "image/jpeg",
+            ImageType::Png => "image/png",
+            ImageType::Webp => "image/webp",
+        }
+    }
+}
+
+// Read the `photo` field of the upload, enforcing the size limit while
+// streaming and checking the declared type against the file's contents
+async fn read_photo(mut payload: Multipart) -> Result<(Vec<u8>, ImageType), AppError> {
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|e| AppError::BadRequest(e.to_string()))?;
+        if field.name() != Some(PHOTO_FIELD) {
+            continue;
+        }
+
+        let declared_type = field
+            .content_type()
+            .and_then(|mime| ImageType::from_content_type(mime.essence_str()))
+            .ok_or_else(|| {
+                AppError::BadRequest("Photo must be a JPEG, PNG or WebP image".to_string())
+            })?;
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|e| AppError::BadRequest(e.to_string()))?;
+            if bytes.len() + chunk.len() > MAX_PHOTO_BYTES {
+                return Err(AppError::BadRequest(format!(
+                    "Photo must be at most {} MB",
+                    MAX_PHOTO_BYTES / (1024 * 1024)
+                )));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        return match ImageType::sniff(&bytes) {
+            Some(image_type) if image_type == declared_type => Ok((bytes, image_type)),
+            _ => Err(AppError::BadRequest(
+                "Photo content does not match its content type".to_string(),
+            )),
+        };
+    }
+
+    Err(AppError::BadRequest("Missing photo field".to_string()))
+}
+
+fn too_many_photos() -> AppError {
+    AppError::BadRequest(format!(
+        "You can upload at most {} photos",
+        MAX_PHOTOS_PER_USER
+    ))
+}
+
+// Serialise photo changes per user so counts and positions stay consistent
+async fn lock_photos(tx: &mut Transaction<'_, Postgres>, user_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+        .bind(format!("photos:{}", user_id))
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+// Keep usersdata.profile_picture_url pointing at the primary photo
+pub(super) async fn sync_profile_picture(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE usersdata SET profile_picture_url = (SELECT url FROM photos WHERE user_id = $1 AND is_primary),
+         updated_at = CURRENT_TIMESTAMP WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+pub(super) async fn invalidate_user_data(cache: &Cache, user_id: Uuid) {
+    cache.invalidate(&[CacheKey::UserData(user_id)]).await;
+}
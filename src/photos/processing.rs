@@ -0,0 +1,254 @@
+use std::{io::Cursor, sync::Arc, time::Duration};
+
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageDecoder, ImageReader,
+    Limits, Rgb, RgbImage,
+};
+use sqlx::PgPool;
+use tokio::sync::{mpsc, Mutex};
+use uuid::Uuid;
+
+use super::photos::{invalidate_user_data, sync_profile_picture, PhotoStatus};
+use crate::{cache::Cache, storage::BlobStore};
+
+const QUEUE_CAPACITY: usize = 64;
+const JPEG_QUALITY: u8 = 85;
+// Refuse to decode anything bigger, a small file can still expand to a huge bitmap
+const MAX_IMAGE_DIMENSION: u32 = 12_000;
+const MAX_DECODE_BYTES: u64 = 512 * 1024 * 1024;
+// Processing photos older than this were lost, e.g. to a restart
+const STALE_AFTER_MINUTES: i32 = 10;
+
+pub struct Variant {
+    pub name: &'static str,
+    // Longest side in pixels, smaller images are never upscaled
+    pub max_size: u32,
+}
+
+pub const THUMBNAIL: Variant = Variant {
+    name: "thumb",
+    max_size: 200,
+};
+pub const MEDIUM: Variant = Variant {
+    name: "medium",
+    max_size: 640,
+};
+pub const FULL: Variant = Variant {
+    name: "full",
+    max_size: 1600,
+};
+
+// Where a photo's variant is stored
+pub fn variant_key(user_id: Uuid, photo_id: Uuid, variant: &Variant) -> String {
+    format!("photos/{}/{}/{}.jpg", user_id, photo_id, variant.name)
+}
+
+pub struct ProcessingJob {
+    pub photo_id: Uuid,
+    pub user_id: Uuid,
+    pub bytes: Vec<u8>,
+}
+
+// Handle used by the upload endpoint to queue photos for processing
+#[derive(Clone)]
+pub struct PhotoProcessor {
+    sender: mpsc::Sender<ProcessingJob>,
+}
+
+impl PhotoProcessor {
+    // Queue the job, failing instead of waiting when the queue is full
+    pub fn enqueue(&self, job: ProcessingJob) -> Result<(), ProcessingJob> {
+        self.sender.try_send(job).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(job) | mpsc::error::TrySendError::Closed(job) => job,
+        })
+    }
+}
+
+// Start the workers that turn uploads into resized, metadata free JPEGs.
+// `workers` controls how many photos are processed at once.
+pub fn spawn_photo_processor(
+    db: PgPool,
+    cache: Cache,
+    blob_store: Arc<dyn BlobStore>,
+    workers: usize,
+) -> PhotoProcessor {
+    let (sender, receiver) = mpsc::channel::<ProcessingJob>(QUEUE_CAPACITY);
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    for _ in 0..workers.max(1) {
+        let receiver = receiver.clone();
+        let db = db.clone();
+        let cache = cache.clone();
+        let blob_store = blob_store.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let Some(job) = receiver.lock().await.recv().await else {
+                    break;
+                };
+                let photo_id = job.photo_id;
+                if let Err(e) = process(&db, &cache, &*blob_store, job).await {
+                    println!("Failed to process photo {}: {}", photo_id, e);
+                    mark_failed(&db, photo_id).await;
+                }
+            }
+        });
+    }
+
+    PhotoProcessor { sender }
+}
+
+async fn process(
+    db: &PgPool,
+    cache: &Cache,
+    blob_store: &dyn BlobStore,
+    job: ProcessingJob,
+) -> Result<(), String> {
+    let ProcessingJob {
+        photo_id,
+        user_id,
+        bytes,
+    } = job;
+
+    // Decoding and resizing are CPU bound, keep them off the async runtime
+    let rendered = tokio::task::spawn_blocking(move || render_variants(&bytes))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let mut keys = Vec::new();
+    for (variant, bytes) in [&THUMBNAIL, &MEDIUM, &FULL].into_iter().zip(rendered) {
+        let key = variant_key(user_id, photo_id, variant);
+        blob_store
+            .put(&key, &bytes, "image/jpeg")
+            .await
+            .map_err(|e| e.to_string())?;
+        keys.push(key);
+    }
+
+    let mut tx = db.begin().await.map_err(|e| e.to_string())?;
+    let updated = sqlx::query(
+        "UPDATE photos SET status = $1, content_type = 'image/jpeg', thumbnail_url = $2, medium_url = $3, url = $4
+         WHERE id = $5 AND status = $6",
+    )
+    .bind(PhotoStatus::Ready)
+    .bind(blob_store.url(&keys[0]))
+    .bind(blob_store.url(&keys[1]))
+    .bind(blob_store.url(&keys[2]))
+    .bind(photo_id)
+    .bind(PhotoStatus::Processing)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    // Deleted (or given up on) while we were working, drop what we stored
+    if updated.rows_affected() == 0 {
+        for key in &keys {
+            let _ = blob_store.delete(key).await;
+        }
+        return Ok(());
+    }
+
+    sync_profile_picture(&mut tx, user_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    invalidate_user_data(cache, user_id).await;
+
+    Ok(())
+}
+
+// Decode, auto-rotate and re-encode the upload once per variant. Re-encoding
+// from pixels drops EXIF, GPS and any other metadata the original carried.
+fn render_variants(bytes: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().map_err(|e| e.to_string())?;
+    let orientation = decoder.orientation().map_err(|e| e.to_string())?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    image.apply_orientation(orientation);
+    let image = flatten(image);
+
+    [&THUMBNAIL, &MEDIUM, &FULL]
+        .into_iter()
+        .map(|variant| encode_jpeg(&image, variant.max_size))
+        .collect()
+}
+
+// JPEG has no alpha channel, so put transparent images on a white background
+fn flatten(image: DynamicImage) -> RgbImage {
+    if !image.color().has_alpha() {
+        return image.to_rgb8();
+    }
+    let rgba = image.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u16 * a as u16 + 255 * (255 - a as u16)) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+fn encode_jpeg(image: &RgbImage, max_size: u32) -> Result<Vec<u8>, String> {
+    let resized;
+    let image = if image.width() > max_size || image.height() > max_size {
+        resized = DynamicImage::ImageRgb8(image.clone())
+            .resize(max_size, max_size, FilterType::Lanczos3)
+            .to_rgb8();
+        &resized
+    } else {
+        image
+    };
+
+    let mut bytes = Vec::new();
+    JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY)
+        .encode_image(image)
+        .map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+async fn mark_failed(db: &PgPool, photo_id: Uuid) {
+    let failed = sqlx::query("UPDATE photos SET status = $1 WHERE id = $2 AND status = $3")
+        .bind(PhotoStatus::Failed)
+        .bind(photo_id)
+        .bind(PhotoStatus::Processing)
+        .execute(db)
+        .await;
+    if let Err(e) = failed {
+        println!("Failed to mark photo {} as failed: {}", photo_id, e);
+    }
+}
+
+// Uploads only live in memory until processed, so a photo left Processing
+// after a restart will never finish. Periodically mark those as Failed so
+// the user knows to upload again.
+pub fn spawn_stale_photo_sweep(db: PgPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5 * 60));
+        loop {
+            interval.tick().await;
+
+            let failed = sqlx::query(
+                "UPDATE photos SET status = $1 WHERE status = $2 AND created_at < NOW() - make_interval(mins => $3)",
+            )
+            .bind(PhotoStatus::Failed)
+            .bind(PhotoStatus::Processing)
+            .bind(STALE_AFTER_MINUTES)
+            .execute(&db)
+            .await;
+
+            match failed {
+                Ok(result) if result.rows_affected() > 0 => {
+                    println!("Marked {} stale photos as failed", result.rows_affected());
+                }
+                Ok(_) => {}
+                Err(e) => println!("Failed to sweep stale photos: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,2 @@
+pub mod preferences;
+pub use preferences::{Preferences, MAXIMUM_DISTANCE_KM};
@@ -0,0 +1,223 @@
+use std::str::FromStr;
+
+use actix_web::{
+    web::{Data, Json},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool};
+use strum_macros::Display;
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthenticatedUser,
+    cache::{Cache, CacheKey},
+    common::{AppError, ResponseToSend},
+    config::{config::ProfileSettings, Settings},
+    user::Gender,
+};
+
+// Largest search radius a user can ask for
+pub const MAXIMUM_DISTANCE_KM: i32 = 500;
+
+#[derive(sqlx::Type, Debug, Deserialize, Display, Serialize, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum RelationshipIntent {
+    LongTerm,
+    ShortTerm,
+    Casual,
+    Friendship,
+    NotSure,
+}
+
+// Genders are stored as a TEXT[] column
+#[derive(FromRow)]
+struct PreferencesRow {
+    wanted_genders: Vec<String>,
+    min_age: i32,
+    max_age: i32,
+    max_distance_km: Option<i32>,
+    intent: Option<RelationshipIntent>,
+}
+
+// Who the user wants to see. Users without saved preferences get the
+// defaults, which match everyone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Preferences {
+    // Empty means any gender
+    pub wanted_genders: Vec<Gender>,
+    pub min_age: i32,
+    pub max_age: i32,
+    // No limit when None
+    pub max_distance_km: Option<i32>,
+    pub intent: Option<RelationshipIntent>,
+}
+
+// Body of PUT /preferences, the age range defaults to the one allowed on
+// the platform
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct UpdatePreferences {
+    wanted_genders: Vec<Gender>,
+    min_age: Option<i32>,
+    max_age: Option<i32>,
+    max_distance_km: Option<i32>,
+    intent: Option<RelationshipIntent>,
+}
+
+impl UpdatePreferences {
+    fn into_preferences(self, profile: &ProfileSettings) -> Preferences {
+        Preferences {
+            wanted_genders: self.wanted_genders,
+            min_age: self.min_age.unwrap_or(profile.min_age),
+            max_age: self.max_age.unwrap_or(profile.max_age),
+            max_distance_km: self.max_distance_km,
+            intent: self.intent,
+        }
+    }
+}
+
+impl From<PreferencesRow> for Preferences {
+    fn from(row: PreferencesRow) -> Self {
+        Preferences {
+            wanted_genders: row
+                .wanted_genders
+                .iter()
+                .filter_map(|gender| Gender::from_str(gender).ok())
+                .collect(),
+            min_age: row.min_age,
+            max_age: row.max_age,
+            max_distance_km: row.max_distance_km,
+            intent: row.intent,
+        }
+    }
+}
+
+impl Preferences {
+    pub fn defaults(profile: &ProfileSettings) -> Self {
+        UpdatePreferences::default().into_preferences(profile)
+    }
+
+    // Load the user's preferences, from Redis when cached. Users who never
+    // saved any are cached as such and get the defaults.
+    pub async fn for_user(
+        db: &PgPool,
+        cache: &Cache,
+        profile: &ProfileSettings,
+        user_id: Uuid,
+    ) -> Result<Preferences, sqlx::Error> {
+        let preferences = cache
+            .get_or_load(CacheKey::Preferences(user_id), || async {
+                sqlx::query_as::<_, PreferencesRow>(
+                    "SELECT wanted_genders, min_age, max_age, max_distance_km, intent FROM user_preferences WHERE user_id = $1",
+                )
+                .bind(user_id)
+                .fetch_optional(db)
+                .await
+                .map(|row| row.map(Preferences::from))
+            })
+            .await?;
+
+        Ok(preferences.unwrap_or_else(|| Preferences::defaults(profile)))
+    }
+
+    fn validate(&self, profile: &ProfileSettings) -> Result<(), String> {
+        if self.min_age < profile.min_age {
+            return Err(format!("Minimum age must be at least {}", profile.min_age));
+        }
+        if self.max_age > profile.max_age {
+            return Err(format!("Maximum age must be at most {}", profile.max_age));
+        }
+        if self.min_age > self.max_age {
+            return Err("Minimum age must not be greater than maximum age".to_string());
+        }
+        if let Some(distance) = self.max_distance_km {
+            if !(1..=MAXIMUM_DISTANCE_KM).contains(&distance) {
+                return Err(format!(
+                    "Maximum distance must be between 1 and {} km",
+                    MAXIMUM_DISTANCE_KM
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Get Preferences
+    pub async fn get_preferences(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        settings: Data<Settings>,
+        auth_user: AuthenticatedUser,
+    ) -> Result<HttpResponse, AppError> {
+        let preferences = Self::for_user(&db, &cache, &settings.profile, auth_user.user_id).await?;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Preferences Fetch Successfully".to_string(),
+            data: Some(preferences),
+        }))
+    }
+
+    // Replace Preferences, fields left out fall back to their defaults
+    pub async fn update_preferences(
+        db: Data<PgPool>,
+        cache: Data<Cache>,
+        settings: Data<Settings>,
+        auth_user: AuthenticatedUser,
+        preferences: Json<UpdatePreferences>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+        let mut preferences = preferences.into_inner().into_preferences(&settings.profile);
+
+        preferences
+            .validate(&settings.profile)
+            .map_err(AppError::BadRequest)?;
+
+        let mut unique_genders: Vec<Gender> = Vec::new();
+        for gender in preferences.wanted_genders {
+            if !unique_genders.contains(&gender) {
+                unique_genders.push(gender);
+            }
+        }
+        preferences.wanted_genders = unique_genders;
+        let wanted_genders: Vec<String> = preferences
+            .wanted_genders
+            .iter()
+            .map(|gender| gender.to_string())
+            .collect();
+
+        sqlx::query(
+            "INSERT INTO user_preferences (user_id, wanted_genders, min_age, max_age, max_distance_km, intent)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (user_id) DO UPDATE SET
+                wanted_genders = EXCLUDED.wanted_genders,
+                min_age = EXCLUDED.min_age,
+                max_age = EXCLUDED.max_age,
+                max_distance_km = EXCLUDED.max_distance_km,
+                intent = EXCLUDED.intent,
+                updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(user_id)
+        .bind(&wanted_genders)
+        .bind(preferences.min_age)
+        .bind(preferences.max_age)
+        .bind(preferences.max_distance_km)
+        .bind(preferences.intent)
+        .execute(&**db)
+        .await?;
+
+        // Drop the cached preferences and the feed that was built from them
+        cache
+            .invalidate(&[
+                CacheKey::Preferences(user_id),
+                CacheKey::DiscoverFeed(user_id),
+            ])
+            .await;
+
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Preferences Updated Successfully".to_string(),
+            data: Some(preferences),
+        }))
+    }
+}
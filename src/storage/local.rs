@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+
+use super::storage::{validate_key, BlobStore, StorageError};
+
+// Keeps blobs on the local filesystem. The directory is served by the app
+// itself, which is fine for development and single-node deployments.
+pub struct LocalBlobStore {
+    root: PathBuf,
+    public_url: String,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>, public_url: String) -> Self {
+        LocalBlobStore {
+            root: root.into(),
+            public_url: public_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, bytes: &[u8], _content_type: &str) -> Result<(), StorageError> {
+        validate_key(key)?;
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, bytes).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        validate_key(key)?;
+        Ok(tokio::fs::read(self.root.join(key)).await?)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        validate_key(key)?;
+        match tokio::fs::remove_file(self.root.join(key)).await {
+            Ok(_) => Ok(()),
+            // Already gone is as good as deleted
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+}
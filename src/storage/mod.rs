@@ -0,0 +1,7 @@
+pub mod local;
+pub mod s3;
+pub mod storage;
+pub use storage::{
+    blob_store_from_settings, local_media_dir, private_blob_store_from_settings, BlobStore,
+    PrivateBlobStore, MEDIA_ROUTE,
+};
@@ -0,0 +1,72 @@
+use async_trait::async_trait;
+use s3::{creds::Credentials, Bucket, Region};
+
+use super::storage::{validate_key, BlobStore, StorageError};
+
+// Keeps blobs in an S3 compatible bucket. Path-style addressing is used so
+// MinIO and other self-hosted stand-ins work without wildcard DNS.
+pub struct S3BlobStore {
+    bucket: Box<Bucket>,
+    public_url: String,
+}
+
+impl S3BlobStore {
+    pub fn new(
+        bucket: &str,
+        region: &str,
+        endpoint: &str,
+        access_key: &str,
+        secret_key: &str,
+        public_url: String,
+    ) -> Result<Self, StorageError> {
+        let region = Region::Custom {
+            region: region.to_string(),
+            endpoint: endpoint.to_string(),
+        };
+        let credentials = Credentials::new(Some(access_key), Some(secret_key), None, None, None)
+            .map_err(|e| StorageError::Backend(e.to_string()))?;
+        let bucket = Bucket::new(bucket, region, credentials)
+            .map_err(|e| StorageError::Backend(e.to_string()))?
+            .with_path_style();
+
+        Ok(S3BlobStore {
+            bucket,
+            public_url: public_url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, key: &str, bytes: &[u8], content_type: &str) -> Result<(), StorageError> {
+        validate_key(key)?;
+        self.bucket
+            .put_object_with_content_type(key, bytes, content_type)
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        validate_key(key)?;
+        let response = self
+            .bucket
+            .get_object(key)
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))?;
+        Ok(response.bytes().to_vec())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        validate_key(key)?;
+        self.bucket
+            .delete_object(key)
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))?;
+        Ok(())
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+}
@@ -0,0 +1,145 @@
+use std::{fmt, ops::Deref, sync::Arc};
+
+use async_trait::async_trait;
+
+use super::{local::LocalBlobStore, s3::S3BlobStore};
+use crate::config::config::{StorageBackend, StorageSettings};
+
+// URL prefix the local store is served under
+pub const MEDIA_ROUTE: &str = "/media";
+
+#[derive(Debug)]
+pub enum StorageError {
+    InvalidKey(String),
+    Backend(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::InvalidKey(key) => write!(f, "invalid storage key: {}", key),
+            StorageError::Backend(e) => write!(f, "storage backend error: {}", e),
+            StorageError::Io(e) => write!(f, "storage io error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+// Somewhere to keep uploaded files. Keys are relative paths such as
+// `photos/<user_id>/<photo_id>.jpg`.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(&self, key: &str, bytes: &[u8], content_type: &str) -> Result<(), StorageError>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError>;
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+    // Public URL clients can fetch the blob from
+    fn url(&self, key: &str) -> String;
+}
+
+// Pick the store from the configured backend. `server_url` is where the
+// app itself is reachable, locally stored files are served from there.
+pub fn blob_store_from_settings(
+    settings: &StorageSettings,
+    server_url: &str,
+) -> Arc<dyn BlobStore> {
+    match settings.backend {
+        StorageBackend::S3 => {
+            // Checked when the settings were loaded
+            let bucket = settings.s3_bucket.as_deref().unwrap_or_default();
+            let endpoint = settings.s3_endpoint.as_deref().unwrap_or_default();
+            let public_url = settings
+                .public_url
+                .clone()
+                .unwrap_or_else(|| format!("{}/{}", endpoint.trim_end_matches('/'), bucket));
+
+            Arc::new(
+                S3BlobStore::new(
+                    bucket,
+                    &settings.s3_region,
+                    endpoint,
+                    settings.s3_access_key.as_deref().unwrap_or_default(),
+                    settings.s3_secret_key.as_deref().unwrap_or_default(),
+                    public_url,
+                )
+                .expect("Failed to create S3 blob store"),
+            )
+        }
+        StorageBackend::Local => {
+            let public_url = settings
+                .public_url
+                .clone()
+                .unwrap_or_else(|| format!("{}{}", server_url, MEDIA_ROUTE));
+            Arc::new(LocalBlobStore::new(settings.local_dir.clone(), public_url))
+        }
+    }
+}
+
+// Store for files only the app hands out, e.g. data exports. Nothing in it
+// is served under MEDIA_ROUTE or readable from a public URL.
+#[derive(Clone)]
+pub struct PrivateBlobStore(Arc<dyn BlobStore>);
+
+impl Deref for PrivateBlobStore {
+    type Target = dyn BlobStore;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+pub fn private_blob_store_from_settings(settings: &StorageSettings) -> PrivateBlobStore {
+    let blob_store: Arc<dyn BlobStore> = match settings.backend {
+        StorageBackend::S3 => {
+            // Checked when the settings were loaded
+            let bucket = settings.s3_private_bucket.as_deref().unwrap_or_default();
+            let endpoint = settings.s3_endpoint.as_deref().unwrap_or_default();
+
+            Arc::new(
+                S3BlobStore::new(
+                    bucket,
+                    &settings.s3_region,
+                    endpoint,
+                    settings.s3_access_key.as_deref().unwrap_or_default(),
+                    settings.s3_secret_key.as_deref().unwrap_or_default(),
+                    format!("{}/{}", endpoint.trim_end_matches('/'), bucket),
+                )
+                .expect("Failed to create private S3 blob store"),
+            )
+        }
+        // Not served, the URL is never handed out
+        StorageBackend::Local => Arc::new(LocalBlobStore::new(
+            settings.private_dir.clone(),
+            String::new(),
+        )),
+    };
+    PrivateBlobStore(blob_store)
+}
+
+// Directory to serve under MEDIA_ROUTE, only when files are stored locally
+pub fn local_media_dir(settings: &StorageSettings) -> Option<String> {
+    match settings.backend {
+        StorageBackend::S3 => None,
+        StorageBackend::Local => Some(settings.local_dir.clone()),
+    }
+}
+
+// Keys are built by us, but never let one escape the store's root
+pub fn validate_key(key: &str) -> Result<(), StorageError> {
+    let is_valid = !key.is_empty()
+        && !key.starts_with('/')
+        && key
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != "..");
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(StorageError::InvalidKey(key.to_string()))
+    }
+}
@@ -0,0 +1,174 @@
+use std::{sync::Arc, time::Duration};
+
+use redis::aio::ConnectionManager;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::export::DataExports;
+use crate::{
+    auth::AccountStatus,
+    cache::{Cache, CacheTag},
+    chat::unread,
+    config::config::AccountSettings,
+    matching::{matching::lock_pair, Matching},
+    photos::Photos,
+    storage::{BlobStore, PrivateBlobStore},
+};
+
+const PURGE_BATCH_SIZE: i64 = 100;
+
+// Mark the account deleted and end its matches. Returns the users it was
+// matched with, or None when it was already deleted.
+pub async fn soft_delete(db: &PgPool, user_id: Uuid) -> Result<Option<Vec<Uuid>>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    let deleted = sqlx::query(
+        "UPDATE users SET status = $2, deleted_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+         WHERE id = $1 AND status <> $2",
+    )
+    .bind(user_id)
+    .bind(AccountStatus::Deleted)
+    .execute(&mut *tx)
+    .await?;
+    if deleted.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    let matched_ids: Vec<Uuid> = sqlx::query_scalar(
+        "SELECT CASE WHEN user_one_id = $1 THEN user_two_id ELSE user_one_id END
+         FROM matches WHERE user_one_id = $1 OR user_two_id = $1",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    // Removes the conversations too
+    for &other_user_id in &matched_ids {
+        lock_pair(&mut tx, user_id, other_user_id).await?;
+        Matching::end_match(&mut tx, user_id, other_user_id).await?;
+    }
+
+    tx.commit().await?;
+    Ok(Some(matched_ids))
+}
+
+// Everything kept for the user outside the database. Collect it before the
+// account is deleted, the rows go with it.
+pub struct StorageKeys {
+    // In the public store
+    photos: Vec<String>,
+    // In the private store
+    exports: Vec<String>,
+}
+
+pub async fn storage_keys(db: &PgPool, user_id: Uuid) -> Result<StorageKeys, sqlx::Error> {
+    Ok(StorageKeys {
+        photos: Photos::storage_keys(db, user_id).await?,
+        exports: DataExports::storage_keys(db, user_id).await?,
+    })
+}
+
+pub async fn delete_blobs(
+    blob_store: &dyn BlobStore,
+    private_store: &PrivateBlobStore,
+    storage_keys: &StorageKeys,
+) {
+    let blobs = storage_keys
+        .photos
+        .iter()
+        .map(|key| (blob_store, key))
+        .chain(
+            storage_keys
+                .exports
+                .iter()
+                .map(|key| (&**private_store, key)),
+        );
+    for (store, storage_key) in blobs {
+        if let Err(e) = store.delete(storage_key).await {
+            println!("Failed to delete blob {}: {}", storage_key, e);
+        }
+    }
+}
+
+// Drop every cache entry about the user
+pub async fn evict_caches(cache: &Cache, redis: &ConnectionManager, user_id: Uuid) {
+    cache.invalidate_tag(CacheTag::User(user_id)).await;
+    unread::invalidate(redis, &[user_id]).await;
+}
+
+// Periodically purge accounts deleted more than `deletion_grace_days` ago,
+// along with their photos and exports.
+// `purge_interval_minutes` controls how often this runs.
+pub fn spawn_account_purge(
+    db: PgPool,
+    redis: ConnectionManager,
+    cache: Cache,
+    blob_store: Arc<dyn BlobStore>,
+    private_store: PrivateBlobStore,
+    settings: &AccountSettings,
+) {
+    let grace_days = settings.deletion_grace_days as i32;
+    let interval_minutes = settings.purge_interval_minutes;
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_minutes * 60));
+        loop {
+            interval.tick().await;
+
+            match purge_deleted_accounts(
+                &db,
+                &redis,
+                &cache,
+                &*blob_store,
+                &private_store,
+                grace_days,
+            )
+            .await
+            {
+                Ok(purged) if purged > 0 => println!("Purged {} deleted accounts", purged),
+                Ok(_) => {}
+                Err(e) => println!("Failed to purge deleted accounts: {}", e),
+            }
+        }
+    });
+}
+
+async fn purge_deleted_accounts(
+    db: &PgPool,
+    redis: &ConnectionManager,
+    cache: &Cache,
+    blob_store: &dyn BlobStore,
+    private_store: &PrivateBlobStore,
+    grace_days: i32,
+) -> Result<usize, sqlx::Error> {
+    let user_ids: Vec<Uuid> = sqlx::query_scalar(
+        "SELECT id FROM users
+         WHERE status = $1 AND deleted_at < NOW() - make_interval(days => $2)
+         ORDER BY deleted_at
+         LIMIT $3",
+    )
+    .bind(AccountStatus::Deleted)
+    .bind(grace_days)
+    .bind(PURGE_BATCH_SIZE)
+    .fetch_all(db)
+    .await?;
+
+    let mut purged = 0;
+    for user_id in user_ids {
+        let storage_keys = storage_keys(db, user_id).await?;
+
+        let deleted = sqlx::query("DELETE FROM users WHERE id = $1 AND status = $2")
+            .bind(user_id)
+            .bind(AccountStatus::Deleted)
+            .execute(db)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            continue;
+        }
+
+        delete_blobs(blob_store, private_store, &storage_keys).await;
+        evict_caches(cache, redis, user_id).await;
+        purged += 1;
+    }
+    Ok(purged)
+}
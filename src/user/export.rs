@@ -0,0 +1,449 @@
+use std::{
+    io::{Cursor, Write},
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web::{Data, Path},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool};
+use strum_macros::Display;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    auth::AuthenticatedUser,
+    common::{AppError, ResponseToSend},
+    storage::{BlobStore, PrivateBlobStore},
+};
+
+const QUEUE_CAPACITY: usize = 16;
+// Pending exports older than this were lost, e.g. to a restart
+const STALE_AFTER_MINUTES: i32 = 30;
+const CLEANUP_BATCH_SIZE: i64 = 100;
+
+// Each JSON file of the archive, built by the database from the user's rows
+const SECTIONS: &[(&str, &str)] = &[
+    (
+        "account.json",
+        "SELECT to_jsonb(a) FROM (
+            SELECT id, username, email, role, email_verified_at, created_at, updated_at FROM users WHERE id = $1
+         ) a",
+    ),
+    (
+        "profile.json",
+        "SELECT to_jsonb(p) FROM (
+            SELECT firstname, lastname, age, gender, bio, city, latitude, longitude, location_updated_at, created_at, updated_at
+            FROM usersdata WHERE user_id = $1
+         ) p",
+    ),
+    (
+        "interests.json",
+        "SELECT COALESCE(jsonb_agg(to_jsonb(i) ORDER BY i.name), '[]') FROM (
+            SELECT c.slug, c.name, c.category, ui.created_at
+            FROM user_interests ui JOIN interests c ON c.id = ui.interest_id WHERE ui.user_id = $1
+         ) i",
+    ),
+    (
+        "preferences.json",
+        "SELECT to_jsonb(p) FROM (
+            SELECT wanted_genders, min_age, max_age, max_distance_km, intent, created_at, updated_at
+            FROM user_preferences WHERE user_id = $1
+         ) p",
+    ),
+    (
+        "photos.json",
+        "SELECT COALESCE(jsonb_agg(to_jsonb(p) ORDER BY p.position), '[]') FROM (
+            SELECT id, position, is_primary, status, created_at FROM photos WHERE user_id = $1
+         ) p",
+    ),
+    (
+        "swipes.json",
+        "SELECT COALESCE(jsonb_agg(to_jsonb(l) ORDER BY l.created_at), '[]') FROM (
+            SELECT likee_id AS user_id, kind, created_at FROM likes WHERE liker_id = $1
+         ) l",
+    ),
+    (
+        "matches.json",
+        "SELECT COALESCE(jsonb_agg(to_jsonb(m) ORDER BY m.matched_at), '[]') FROM (
+            SELECT id, CASE WHEN user_one_id = $1 THEN user_two_id ELSE user_one_id END AS user_id, created_at AS matched_at
+            FROM matches WHERE user_one_id = $1 OR user_two_id = $1
+         ) m",
+    ),
+    (
+        "messages.json",
+        "SELECT COALESCE(jsonb_agg(to_jsonb(m) ORDER BY m.created_at), '[]') FROM (
+            SELECT id, conversation_id, body, status, created_at, delivered_at, read_at FROM messages WHERE sender_id = $1
+         ) m",
+    ),
+    (
+        "blocks.json",
+        "SELECT COALESCE(jsonb_agg(to_jsonb(b) ORDER BY b.created_at), '[]') FROM (
+            SELECT blocked_id AS user_id, created_at FROM blocks WHERE blocker_id = $1
+         ) b",
+    ),
+    (
+        "reports.json",
+        "SELECT COALESCE(jsonb_agg(to_jsonb(r) ORDER BY r.created_at), '[]') FROM (
+            SELECT id, reported_id AS user_id, reason, details, status, created_at FROM reports WHERE reporter_id = $1
+         ) r",
+    ),
+];
+
+#[derive(sqlx::Type, Debug, Deserialize, Display, Serialize, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+#[derive(FromRow, Serialize, Debug)]
+pub struct DataExport {
+    id: Uuid,
+    status: ExportStatus,
+    size_bytes: Option<i64>,
+    created_at: DateTime<Utc>,
+    completed_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    // Only set once the archive is ready
+    #[sqlx(skip)]
+    download_url: Option<String>,
+}
+
+pub struct ExportJob {
+    pub export_id: Uuid,
+    pub user_id: Uuid,
+}
+
+// Handle used by the export endpoint to queue archives to build
+#[derive(Clone)]
+pub struct DataExporter {
+    sender: mpsc::Sender<ExportJob>,
+}
+
+impl DataExporter {
+    // Queue the job, failing instead of waiting when the queue is full
+    pub fn enqueue(&self, job: ExportJob) -> Result<(), ExportJob> {
+        self.sender.try_send(job).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(job) | mpsc::error::TrySendError::Closed(job) => job,
+        })
+    }
+}
+
+// Start the worker building data exports. Photos are read from `blob_store`,
+// archives are kept in `private_store` and can be downloaded for `ttl_hours`
+// once ready.
+pub fn spawn_data_exporter(
+    db: PgPool,
+    blob_store: Arc<dyn BlobStore>,
+    private_store: PrivateBlobStore,
+    ttl_hours: i32,
+) -> DataExporter {
+    let (sender, mut receiver) = mpsc::channel::<ExportJob>(QUEUE_CAPACITY);
+
+    // One at a time, an export reads every row of the user
+    tokio::spawn(async move {
+        while let Some(job) = receiver.recv().await {
+            let export_id = job.export_id;
+            if let Err(e) = export(&db, &*blob_store, &private_store, job, ttl_hours).await {
+                println!("Failed to build data export {}: {}", export_id, e);
+                mark_failed(&db, export_id).await;
+            }
+        }
+    });
+
+    DataExporter { sender }
+}
+
+async fn export(
+    db: &PgPool,
+    blob_store: &dyn BlobStore,
+    private_store: &PrivateBlobStore,
+    job: ExportJob,
+    ttl_hours: i32,
+) -> Result<(), String> {
+    let archive = build_archive(db, blob_store, job.user_id).await?;
+    let storage_key = export_key(job.user_id, job.export_id);
+
+    private_store
+        .put(&storage_key, &archive, "application/zip")
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let ready = sqlx::query(
+        "UPDATE data_exports
+         SET status = $1, storage_key = $2, size_bytes = $3, completed_at = CURRENT_TIMESTAMP,
+             expires_at = CURRENT_TIMESTAMP + make_interval(hours => $4)
+         WHERE id = $5",
+    )
+    .bind(ExportStatus::Ready)
+    .bind(&storage_key)
+    .bind(archive.len() as i64)
+    .bind(ttl_hours)
+    .bind(job.export_id)
+    .execute(db)
+    .await;
+
+    if let Err(e) = ready {
+        // Nothing points at the archive, don't leave it behind
+        if let Err(e) = private_store.delete(&storage_key).await {
+            println!("Failed to delete data export {}: {}", storage_key, e);
+        }
+        return Err(e.to_string());
+    }
+    Ok(())
+}
+
+// Zip every section of the user's data along with their photos
+async fn build_archive(
+    db: &PgPool,
+    blob_store: &dyn BlobStore,
+    user_id: Uuid,
+) -> Result<Vec<u8>, String> {
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+
+    for (name, query) in SECTIONS {
+        let section: Option<serde_json::Value> = sqlx::query_scalar(query)
+            .bind(user_id)
+            .fetch_optional(db)
+            .await
+            .map_err(|e| e.to_string())?;
+        let json =
+            serde_json::to_vec_pretty(&section.unwrap_or_default()).map_err(|e| e.to_string())?;
+        files.push((name.to_string(), json));
+    }
+
+    let photos: Vec<(Uuid, String)> = sqlx::query_as(
+        "SELECT id, storage_key FROM photos WHERE user_id = $1 AND status = 'Ready'",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await
+    .map_err(|e| e.to_string())?;
+    for (photo_id, storage_key) in photos {
+        let bytes = blob_store
+            .get(&storage_key)
+            .await
+            .map_err(|e| e.to_string())?;
+        files.push((format!("photos/{}.jpg", photo_id), bytes));
+    }
+
+    tokio::task::spawn_blocking(move || write_archive(files))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+fn write_archive(files: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>, String> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+    for (name, bytes) in files {
+        // Photos are JPEGs already, compressing them again gains nothing
+        let method = if name.ends_with(".json") {
+            CompressionMethod::Deflated
+        } else {
+            CompressionMethod::Stored
+        };
+        writer
+            .start_file(
+                name,
+                SimpleFileOptions::default().compression_method(method),
+            )
+            .map_err(|e| e.to_string())?;
+        writer.write_all(&bytes).map_err(|e| e.to_string())?;
+    }
+
+    let archive = writer.finish().map_err(|e| e.to_string())?;
+    Ok(archive.into_inner())
+}
+
+async fn mark_failed(db: &PgPool, export_id: Uuid) {
+    let failed = sqlx::query(
+        "UPDATE data_exports SET status = $1, completed_at = CURRENT_TIMESTAMP WHERE id = $2 AND status = $3",
+    )
+    .bind(ExportStatus::Failed)
+    .bind(export_id)
+    .bind(ExportStatus::Pending)
+    .execute(db)
+    .await;
+    if let Err(e) = failed {
+        println!("Failed to mark data export {} as failed: {}", export_id, e);
+    }
+}
+
+// Periodically delete expired archives, and give up on exports lost to a
+// restart so the user can ask for a new one
+pub fn spawn_export_cleanup(db: PgPool, private_store: PrivateBlobStore) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(15 * 60));
+        loop {
+            interval.tick().await;
+
+            let failed = sqlx::query(
+                "UPDATE data_exports SET status = $1, completed_at = CURRENT_TIMESTAMP
+                 WHERE status = $2 AND created_at < NOW() - make_interval(mins => $3)",
+            )
+            .bind(ExportStatus::Failed)
+            .bind(ExportStatus::Pending)
+            .bind(STALE_AFTER_MINUTES)
+            .execute(&db)
+            .await;
+            if let Err(e) = failed {
+                println!("Failed to sweep stale data exports: {}", e);
+            }
+
+            if let Err(e) = remove_expired(&db, &private_store).await {
+                println!("Failed to remove expired data exports: {}", e);
+            }
+        }
+    });
+}
+
+async fn remove_expired(db: &PgPool, private_store: &PrivateBlobStore) -> Result<(), sqlx::Error> {
+    let expired: Vec<(Uuid, String)> = sqlx::query_as(
+        "SELECT id, storage_key FROM data_exports
+         WHERE storage_key IS NOT NULL AND expires_at < NOW()
+         LIMIT $1",
+    )
+    .bind(CLEANUP_BATCH_SIZE)
+    .fetch_all(db)
+    .await?;
+
+    for (export_id, storage_key) in expired {
+        // Keep the row when the blob can't be deleted, the next sweep retries
+        if let Err(e) = private_store.delete(&storage_key).await {
+            println!("Failed to delete data export {}: {}", storage_key, e);
+            continue;
+        }
+        sqlx::query("DELETE FROM data_exports WHERE id = $1")
+            .bind(export_id)
+            .execute(db)
+            .await?;
+    }
+    Ok(())
+}
+
+fn export_key(user_id: Uuid, export_id: Uuid) -> String {
+    format!("exports/{}/{}.zip", user_id, export_id)
+}
+
+pub struct DataExports;
+
+impl DataExports {
+    // Get Data Export. Starts building a new archive unless one is already
+    // on its way or still available.
+    pub async fn request_export(
+        db: Data<PgPool>,
+        exporter: Data<DataExporter>,
+        auth_user: AuthenticatedUser,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+
+        let current = sqlx::query_as::<_, DataExport>(
+            "SELECT id, status, size_bytes, created_at, completed_at, expires_at
+             FROM data_exports
+             WHERE user_id = $1
+               AND ((status = $2 AND created_at > NOW() - make_interval(mins => $3))
+                    OR (status = $4 AND expires_at > NOW()))
+             ORDER BY created_at DESC
+             LIMIT 1",
+        )
+        .bind(user_id)
+        .bind(ExportStatus::Pending)
+        .bind(STALE_AFTER_MINUTES)
+        .bind(ExportStatus::Ready)
+        .fetch_optional(&**db)
+        .await?;
+
+        if let Some(mut export) = current {
+            if export.status == ExportStatus::Ready {
+                export.download_url = Some(download_url(export.id));
+            }
+            return Ok(HttpResponse::Ok().json(ResponseToSend {
+                success: true,
+                message: "Data Export Fetch Successfully".to_string(),
+                data: Some(export),
+            }));
+        }
+
+        let export = sqlx::query_as::<_, DataExport>(
+            "INSERT INTO data_exports (id, user_id, status) VALUES ($1, $2, $3)
+             RETURNING id, status, size_bytes, created_at, completed_at, expires_at",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(ExportStatus::Pending)
+        .fetch_one(&**db)
+        .await?;
+
+        let job = ExportJob {
+            export_id: export.id,
+            user_id,
+        };
+        if exporter.enqueue(job).is_err() {
+            mark_failed(&db, export.id).await;
+            return Err(AppError::ServiceUnavailable(
+                "Too many exports in progress, try again later".to_string(),
+            ));
+        }
+
+        Ok(HttpResponse::Accepted().json(ResponseToSend {
+            success: true,
+            message: "Data Export Started".to_string(),
+            data: Some(export),
+        }))
+    }
+
+    // Download Data Export. Archives are private, this is the only way to
+    // fetch them.
+    pub async fn download_export(
+        db: Data<PgPool>,
+        private_store: Data<PrivateBlobStore>,
+        auth_user: AuthenticatedUser,
+        path: Path<Uuid>,
+    ) -> Result<HttpResponse, AppError> {
+        let storage_key: Option<String> = sqlx::query_scalar(
+            "SELECT storage_key FROM data_exports
+             WHERE id = $1 AND user_id = $2 AND status = $3 AND expires_at > NOW()",
+        )
+        .bind(path.into_inner())
+        .bind(auth_user.user_id)
+        .bind(ExportStatus::Ready)
+        .fetch_optional(&**db)
+        .await?;
+
+        let Some(storage_key) = storage_key else {
+            return Err(AppError::NotFound("Data Export Not Found".to_string()));
+        };
+
+        let archive = private_store.get(&storage_key).await?;
+        let filename = format!("amourithm-export-{}.zip", Utc::now().format("%Y-%m-%d"));
+        Ok(HttpResponse::Ok()
+            .content_type("application/zip")
+            .insert_header(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(filename)],
+            })
+            .body(archive))
+    }
+
+    // Archives kept for the user, removed along with the account
+    pub async fn storage_keys(db: &PgPool, user_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT storage_key FROM data_exports WHERE user_id = $1 AND storage_key IS NOT NULL",
+        )
+        .bind(user_id)
+        .fetch_all(db)
+        .await
+    }
+}
+
+fn download_url(export_id: Uuid) -> String {
+    format!("/api/v1/user/export/{}/download", export_id)
+}
@@ -1,2 +1,6 @@
+pub mod deletion;
+pub mod export;
+pub use deletion::spawn_account_purge;
+pub use export::{spawn_data_exporter, spawn_export_cleanup, DataExports};
 pub mod user;
-pub use user::User;
+pub use user::{Gender, User};
@@ -1,272 +1,286 @@
-use std::sync::Arc;
-
 use actix_web::{
     web::{Data, Json},
-    HttpRequest, HttpResponse, Responder,
+    HttpResponse,
 };
-use redis::{aio::MultiplexedConnection, AsyncCommands};
+use chrono::{DateTime, Duration, Utc};
+use redis::aio::ConnectionManager;
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgQueryResult, prelude::FromRow, Error, PgPool, Result};
-use strum_macros::Display;
-use tokio::sync::Mutex;
+use sqlx::{prelude::FromRow, PgPool, Result};
+use strum_macros::{Display, EnumString};
 use uuid::Uuid;
 
+use super::deletion;
 use crate::{
-    auth::jwt::validate_token,
-    common::{
-        handle_bad_request, handle_internal_server_error, handle_not_found_error, ResponseToSend,
-    },
+    auth::{session::revoke_all_sessions, utils::decrypt_password, AuthenticatedUser},
+    cache::{Cache, CacheKey},
+    chat::unread,
+    common::{AppError, ResponseToSend},
+    config::Settings,
+    interests::{Interest, Interests},
+    photos::{Photo, Photos},
 };
 
-#[derive(sqlx::Type, Debug, Deserialize, Display, Serialize)]
+#[derive(
+    sqlx::Type, Debug, Deserialize, Display, EnumString, Serialize, Clone, Copy, PartialEq, Eq,
+)]
 #[sqlx(type_name = "VARCHAR")]
-enum Gender {
+pub enum Gender {
     Male,
     Female,
     Other,
 }
 
-#[derive(Deserialize, Debug, FromRow, Serialize)]
+#[derive(Deserialize, Debug, Default, FromRow, Serialize)]
 pub struct User {
-    firstname: Option<String>,
-    lastname: Option<String>,
-    age: Option<i32>,
-    gender: Option<Gender>,
-    bio: Option<String>,
-    city: Option<String>,
-    profile_picture_url: Option<String>,
+    pub(crate) firstname: Option<String>,
+    pub(crate) lastname: Option<String>,
+    pub(crate) age: Option<i32>,
+    pub(crate) gender: Option<Gender>,
+    pub(crate) bio: Option<String>,
+    pub(crate) city: Option<String>,
+    pub(crate) profile_picture_url: Option<String>,
+    // Loaded separately from user_interests
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub(crate) interests: Vec<Interest>,
 }
 
-#[derive(Deserialize, FromRow, Serialize, Debug)]
-pub struct UserAddress {}
+#[derive(Deserialize, Debug)]
+pub struct DeleteAccount {
+    // Confirms it's the user, not just someone holding their token
+    password: String,
+}
 
-impl User {
-    async fn get_user_basic_data(
-        db: Data<PgPool>,
-        redis: Data<Arc<Mutex<MultiplexedConnection>>>,
-        user_id: Uuid,
-    ) -> Option<User> {
-        let mut redis_conn = redis.lock().await;
-        // println!("user _id {:?}", user_id);
+#[derive(Serialize, Debug)]
+pub struct AccountDeletion {
+    purge_after: DateTime<Utc>,
+}
 
-        let redis_key = format!("user_data:{}", user_id); // Use a unique key
-        let get_user_data_from_redis: Result<Option<String>, redis::RedisError> =
-            redis_conn.get(redis_key.clone()).await;
+// The user's own profile as returned by GET /api/v1/user
+#[derive(Serialize, Debug)]
+pub struct Profile {
+    #[serde(flatten)]
+    user: User,
+    photos: Vec<Photo>,
+}
 
-        match get_user_data_from_redis {
-            Ok(Some(redis_user_data)) => {
-                // println!("Returning from redis");
-                // Deserialize the cached data from Redis
-                match serde_json::from_str::<User>(&redis_user_data) {
-                    Ok(user_data) => Some(user_data),
-                    Err(_) => None, // If deserialization fails, return None
-                }
-            }
-            Ok(None) => {
-                let user_data = sqlx::query_as::<_, User>(
+impl User {
+    // The user's profile, None until they've filled it in
+    pub(crate) async fn get_user_basic_data(
+        db: &PgPool,
+        cache: &Cache,
+        user_id: Uuid,
+    ) -> Result<Option<User>, sqlx::Error> {
+        cache
+            .get_or_load(CacheKey::UserData(user_id), || async {
+                let Some(mut data) = sqlx::query_as::<_, User>(
                     "SELECT firstname, lastname, age, gender, bio, profile_picture_url, city FROM usersdata WHERE user_id = $1",
                 )
-                .bind(user_id) // `id` should be of the correct type (likely `Uuid`)
-                .fetch_one(&**db)
-                .await;
-
-                match user_data {
-                    Ok(data) => {
-                        // println!("Setting in redis");
-
-                        // Serialize the user data to store in Redis
-                        let serialized_data = match serde_json::to_string(&data) {
-                            Ok(json_data) => json_data,
-                            Err(_) => return None, // Return None if serialization fails
-                        };
-
-                        // Store the data in Redis with an expiration time (1 hour)
-                        let _: () = redis_conn
-                            .set_ex(&redis_key, serialized_data, 3600)
-                            .await
-                            .unwrap();
+                .bind(user_id)
+                .fetch_optional(db)
+                .await?
+                else {
+                    return Ok(None);
+                };
 
-                        Some(data)
-                    }
-                    Err(_) => None,
-                }
-            }
-            Err(e) => {
-                // Handle Redis error if any
-                println!("Error fetching from Redis: {}", e);
-                None
-            }
-        }
+                data.interests = Interests::for_user(db, user_id).await?;
+                Ok(Some(data))
+            })
+            .await
     }
 
     // Get User
     pub async fn get_user(
         db: Data<PgPool>,
-        redis: Data<Arc<Mutex<MultiplexedConnection>>>,
-        req: HttpRequest,
-    ) -> impl Responder {
-        match validate_token(req).await {
-            Ok(user_id) => {
-                let user_data = Self::get_user_basic_data(db.clone(), redis.clone(), user_id).await;
+        cache: Data<Cache>,
+        auth_user: AuthenticatedUser,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
+        let user_data = Self::get_user_basic_data(&db, &cache, user_id).await?;
 
-                if let Some(data) = user_data {
-                    HttpResponse::Ok().json(ResponseToSend {
-                        success: true,
-                        message: "User Data Fetch Successully".to_string(),
-                        data: Some(data),
-                    })
-                } else {
-                    return handle_not_found_error("User Data Not Found");
-                }
-            }
-            Err(err) => err,
-        }
-    }
+        let Some(data) = user_data else {
+            return Err(AppError::NotFound("User Data Not Found".to_string()));
+        };
 
-    pub async fn insert_user_data(
-        db: Data<PgPool>,
-        req: HttpRequest,
-        redis: Data<Arc<Mutex<MultiplexedConnection>>>,
-        user: Json<User>,
-    ) -> impl Responder {
-        match validate_token(req).await {
-            Ok(user_id) => {
-                let mut response: Result<PgQueryResult, Error> = Err(sqlx::Error::RowNotFound); // Initialize with a default error or a valid result type
-
-                // Check Firstname
-                if let Some(firstname) = &user.firstname {
-                    let is_user_data_exists: bool = sqlx::query_scalar(
-                        "SELECT EXISTS(SELECT 1 FROM usersdata WHERE user_id = $1)",
-                    )
-                    .bind(user_id)
-                    .fetch_one(&**db)
-                    .await
-                    // .map_err(|_| HttpResponse::InternalServerError().finish())?
-                    .unwrap_or(false);
+        // Photos aren't cached, their processing status changes in the background
+        let photos = Photos::list(&db, user_id).await?;
 
-                    if !is_user_data_exists {
-                        println!("creating user");
-                        let usersdata_id = Uuid::new_v4();
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "User Data Fetch Successully".to_string(),
+            data: Some(Profile { user: data, photos }),
+        }))
+    }
 
-                        response = sqlx::query(
-                            "INSERT INTO usersdata (id, firstname, user_id) VALUES ($1, $2, $3)",
-                        )
-                        .bind(usersdata_id)
-                        .bind(firstname.clone())
-                        .bind(user_id)
-                        .execute(&**db)
-                        .await;
-                    } else {
-                        println!("updating user");
+    // Delete Account. The account disappears right away and is purged with
+    // everything it owns after the grace period.
+    pub async fn delete_account(
+        db: Data<PgPool>,
+        redis: Data<ConnectionManager>,
+        cache: Data<Cache>,
+        settings: Data<Settings>,
+        auth_user: AuthenticatedUser,
+        body: Json<DeleteAccount>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
 
-                        response = sqlx::query(
-                            "UPDATE usersdata SET firstname = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
-                        )
-                            .bind(firstname.clone())
-                            .bind(user_id)
-                            .execute(&**db)
-                            .await;
-                    }
-                }
+        let password: Option<String> =
+            sqlx::query_scalar("SELECT password FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&**db)
+                .await?;
+        match password {
+            Some(password) if decrypt_password(&body.password, &password) => {}
+            Some(_) => return Err(AppError::BadRequest("Password Not Matched".to_string())),
+            None => return Err(AppError::NotFound("User Not Found".to_string())),
+        }
 
-                // Update Lastname
-                if let Some(lastname) = &user.lastname {
-                    response = sqlx::query(
-                        "UPDATE usersdata SET lastname = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
-                    )
-                        .bind(lastname.clone())
-                        .bind(user_id)
-                        .execute(&**db)
-                        .await;
-                }
+        let Some(matched_ids) = deletion::soft_delete(&db, user_id).await? else {
+            return Err(AppError::NotFound("User Not Found".to_string()));
+        };
 
-                // Update Age
-                if let Some(user_age) = user.age {
-                    const MINIMUM_AGE: i32 = 18;
-                    const MAXIMUM_AGE: i32 = 50;
+        // Tokens of a deleted account are refused anyway, this ends the sessions
+        if let Err(e) = revoke_all_sessions(&mut redis.get_ref().clone(), user_id).await {
+            println!("Failed to revoke sessions of deleted user: {}", e);
+        }
+        deletion::evict_caches(&cache, &redis, user_id).await;
+        unread::invalidate(&redis, &matched_ids).await;
 
-                    // Validate age
-                    if user_age < MINIMUM_AGE {
-                        return handle_bad_request("Age must be greater than 18");
-                    } else if user_age > MAXIMUM_AGE {
-                        return handle_bad_request("Age must be less than 50");
-                    } else {
-                        // println!("User age type: {:?}", std::any::type_name::<i8>());
+        Ok(HttpResponse::Ok().json(ResponseToSend {
+            success: true,
+            message: "Account Deleted Successfully".to_string(),
+            data: Some(AccountDeletion {
+                purge_after: Utc::now() + Duration::days(settings.accounts.deletion_grace_days),
+            }),
+        }))
+    }
 
-                        // Update age in the database
-                        response = sqlx::query(
-                                            "UPDATE usersdata SET age = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
-                                        )
-                                        .bind(user_age)
-                                        .bind(user_id)
-                                        .execute(&**db)
-                                        .await;
-                    }
-                }
+    pub async fn insert_user_data(
+        db: Data<PgPool>,
+        auth_user: AuthenticatedUser,
+        cache: Data<Cache>,
+        settings: Data<Settings>,
+        user: Json<User>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_id = auth_user.user_id;
 
-                // Update Gender
-                if let Some(gender) = &user.gender {
-                    let gender_str = match gender {
-                        Gender::Male => "Male",
-                        Gender::Female => "Female",
-                        Gender::Other => "Other",
-                    };
+        let nothing_to_update = user.firstname.is_none()
+            && user.lastname.is_none()
+            && user.age.is_none()
+            && user.gender.is_none()
+            && user.city.is_none()
+            && user.bio.is_none();
+        if nothing_to_update {
+            return Err(AppError::BadRequest("No User Data To Update".to_string()));
+        }
 
-                    // Update the gender in the database
-                    response = sqlx::query(
-                                        "UPDATE usersdata SET gender = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
-                                    )
-                                    .bind(gender_str)  // Binding the gender string
-                                    .bind(user_id)
-                                    .execute(&**db)
-                                    .await;
-                }
+        // Validate age before anything is written
+        if let Some(user_age) = user.age {
+            if user_age < settings.profile.min_age {
+                return Err(AppError::BadRequest(format!(
+                    "Age must be at least {}",
+                    settings.profile.min_age
+                )));
+            } else if user_age > settings.profile.max_age {
+                return Err(AppError::BadRequest(format!(
+                    "Age must be at most {}",
+                    settings.profile.max_age
+                )));
+            }
+        }
 
-                // Update City
-                if let Some(city) = &user.city {
-                    response = sqlx::query(
-                        "UPDATE usersdata SET city = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2"
-                    ).bind(city)
+        // Check Firstname
+        if let Some(firstname) = &user.firstname {
+            let is_user_data_exists: bool =
+                sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM usersdata WHERE user_id = $1)")
                     .bind(user_id)
-                    .execute(&**db)
-                    .await;
-                }
+                    .fetch_one(&**db)
+                    .await?;
 
-                // Update Bio
-                if let Some(bio) = &user.bio {
-                    response = sqlx::query(
-                        "UPDATE usersdata SET bio = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2"
-                    ).bind(bio)
+            if !is_user_data_exists {
+                sqlx::query("INSERT INTO usersdata (id, firstname, user_id) VALUES ($1, $2, $3)")
+                    .bind(Uuid::new_v4())
+                    .bind(firstname)
                     .bind(user_id)
                     .execute(&**db)
-                    .await;
-                }
+                    .await?;
+            } else {
+                sqlx::query(
+                    "UPDATE usersdata SET firstname = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
+                )
+                .bind(firstname)
+                .bind(user_id)
+                .execute(&**db)
+                .await?;
+            }
+        }
 
-                match response {
-                    Ok(_) => {
-                        let mut redis_conn = redis.lock().await;
-                        // println!("user _id {:?}", user_id);
+        // Update Lastname
+        if let Some(lastname) = &user.lastname {
+            sqlx::query(
+                "UPDATE usersdata SET lastname = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
+            )
+            .bind(lastname)
+            .bind(user_id)
+            .execute(&**db)
+            .await?;
+        }
 
-                        let redis_key = format!("user_data:{}", user_id); // Use a unique key
-                        let _: Result<i64, redis::RedisError> =
-                            redis_conn.del(redis_key.clone()).await;
-                        HttpResponse::Ok().json(ResponseToSend::<()> {
-                            success: true,
-                            message: "User Data Updated Successfully".to_string(),
-                            data: None,
-                        })
-                    }
-                    Err(e) => handle_internal_server_error(&e.to_string()),
-                }
-            }
-            Err(e) => e,
+        // Update Age
+        if let Some(user_age) = user.age {
+            sqlx::query(
+                "UPDATE usersdata SET age = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
+            )
+            .bind(user_age)
+            .bind(user_id)
+            .execute(&**db)
+            .await?;
         }
-    }
 
-    // pub async fn update_user_address(
-    //     db: Data<PgPool>,
-    //     req: HttpRequest,
-    //     address: Json<UserAddress>,
-    // ) -> impl Responder {
-    // }
+        // Update Gender
+        if let Some(gender) = &user.gender {
+            let gender_str = match gender {
+                Gender::Male => "Male",
+                Gender::Female => "Female",
+                Gender::Other => "Other",
+            };
+
+            sqlx::query(
+                "UPDATE usersdata SET gender = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
+            )
+            .bind(gender_str)
+            .bind(user_id)
+            .execute(&**db)
+            .await?;
+        }
+
+        // Update City
+        if let Some(city) = &user.city {
+            sqlx::query(
+                "UPDATE usersdata SET city = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
+            )
+            .bind(city)
+            .bind(user_id)
+            .execute(&**db)
+            .await?;
+        }
+
+        // Update Bio
+        if let Some(bio) = &user.bio {
+            sqlx::query(
+                "UPDATE usersdata SET bio = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
+            )
+            .bind(bio)
+            .bind(user_id)
+            .execute(&**db)
+            .await?;
+        }
+
+        cache.invalidate(&[CacheKey::UserData(user_id)]).await;
+        Ok(HttpResponse::Ok().json(ResponseToSend::<()> {
+            success: true,
+            message: "User Data Updated Successfully".to_string(),
+            data: None,
+        }))
+    }
 }